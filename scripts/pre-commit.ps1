@@ -12,4 +12,7 @@ cargo clippy --all-targets -- -D warnings
 if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
 Write-Host "Running cargo test..."
 cargo test
+if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
+Write-Host "Running cargo test --features testing..."
+cargo test --features testing
 exit $LASTEXITCODE
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Address of the client at the other end of a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    // Clients of a Unix socket are usually unnamed.
+    Unix(Option<PathBuf>),
+}
+
+impl PeerAddr {
+    /// The client IP, for TCP peers.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr.ip()),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            Self::Unix(None) => write!(f, "unix:(unnamed)"),
+        }
+    }
+}
+
+/// Where the server listens: `127.0.0.1:8080` or `unix:/run/aegis.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrParseError(String);
+
+impl fmt::Display for AddrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid server address {:?}", self.0)
+    }
+}
+
+impl std::error::Error for AddrParseError {}
+
+impl FromStr for ServerAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err(AddrParseError(s.to_string())),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| AddrParseError(s.to_string())),
+        }
+    }
+}
+
+impl From<SocketAddr> for ServerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_server_addr() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<ServerAddr>(),
+            Ok(ServerAddr::Tcp("127.0.0.1:8080".parse().unwrap()))
+        );
+        assert_eq!(
+            "unix:/run/aegis.sock".parse::<ServerAddr>(),
+            Ok(ServerAddr::Unix(PathBuf::from("/run/aegis.sock")))
+        );
+        assert!("unix:".parse::<ServerAddr>().is_err());
+        assert!("localhost".parse::<ServerAddr>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for addr in ["[::1]:443", "unix:/tmp/a b.sock"] {
+            assert_eq!(addr.parse::<ServerAddr>().unwrap().to_string(), addr);
+        }
+    }
+
+    #[test]
+    fn test_peer_addr() {
+        let tcp = PeerAddr::from("10.0.0.1:5000".parse::<SocketAddr>().unwrap());
+        assert_eq!(tcp.ip(), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(tcp.to_string(), "10.0.0.1:5000");
+
+        let unix = PeerAddr::Unix(None);
+        assert_eq!(unix.ip(), None);
+        assert_eq!(unix.to_string(), "unix:(unnamed)");
+    }
+}
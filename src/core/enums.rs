@@ -8,18 +8,333 @@ pub enum ContentType {
     Unknown,
 }
 
+/// Request method. Methods are case-sensitive, so `get` is an extension
+/// method rather than `Get`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Extension(String),
+}
+
+impl Method {
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "GET" => Self::Get,
+            "HEAD" => Self::Head,
+            "POST" => Self::Post,
+            "PUT" => Self::Put,
+            "DELETE" => Self::Delete,
+            "CONNECT" => Self::Connect,
+            "OPTIONS" => Self::Options,
+            "TRACE" => Self::Trace,
+            "PATCH" => Self::Patch,
+            other => Self::Extension(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Connect => "CONNECT",
+            Self::Options => "OPTIONS",
+            Self::Trace => "TRACE",
+            Self::Patch => "PATCH",
+            Self::Extension(method) => method,
+        }
+    }
+
+    /// Safe methods (RFC 9110, section 9.2.1) do not modify server state.
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Self::Get | Self::Head | Self::Options | Self::Trace)
+    }
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Which of the `ServerConfig` timeouts made the server give up on a
+/// request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    // The request head did not arrive completely in time.
+    HeaderRead,
+    // The client stopped sending the body.
+    BodyRead,
+    // The application did not start a response in time.
+    Response,
+}
+
+impl Timeout {
+    /// Status the server answers with when the timeout expires.
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            Self::HeaderRead | Self::BodyRead => HttpStatus::RequestTimeout,
+            Self::Response => HttpStatus::GatewayTimeout,
+        }
+    }
+}
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HeaderRead => write!(f, "header read timeout"),
+            Self::BodyRead => write!(f, "body read timeout"),
+            Self::Response => write!(f, "response timeout"),
+        }
+    }
+}
+
+/// What happens to a connection that arrives while a connection limit is
+/// reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LimitPolicy {
+    // Hold it until a slot frees up.
+    Wait,
+    // Answer `503 Service Unavailable` and close it.
+    #[default]
+    Reject,
+    // Close it without a response.
+    Close,
+}
+
+impl LimitPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "wait" => Some(Self::Wait),
+            "reject" => Some(Self::Reject),
+            "close" => Some(Self::Close),
+            _ => None,
+        }
+    }
+}
+
+/// How forgiving the HTTP/1.x parser is about message framing. Ambiguous
+/// framing lets a proxy and the server disagree on where a request ends,
+/// which is how requests get smuggled past the proxy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParsingMode {
+    // Everything RFC 9112 allows a server to reject is answered with
+    // `400 Bad Request`.
+    #[default]
+    Strict,
+    // Bare LF line endings are accepted. `Transfer-Encoding` overrides
+    // `Content-Length`, and the connection is closed after the response.
+    // Codings applied before `chunked` are left for the application.
+    Lenient,
+}
+
+impl ParsingMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Some(Self::Strict),
+            "lenient" => Some(Self::Lenient),
+            _ => None,
+        }
+    }
+}
+
+/// Why `accept` failed, which decides how the accept loop reacts.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum HttpStatus {
-    Ok = 200,
-    Created = 201,
-    Accepted = 202,
-    NoContent = 204,
-    BadRequest = 400,
-    Forbidden = 403,
-    NotFound = 404,
-    PayloadTooLarge = 413,
-    InternalServerError = 500,
-    NotImplemented = 501,
+pub enum AcceptErrorKind {
+    // The client went away during the handshake; retry at once.
+    Connection,
+    // Out of file descriptors, buffers or memory; back off and retry.
+    ResourceExhausted,
+    // The listening socket itself is unusable; stop.
+    Fatal,
+    // Anything else; back off and retry.
+    Other,
+}
+
+impl AcceptErrorKind {
+    pub const ALL: [Self; 4] = [
+        Self::Connection,
+        Self::ResourceExhausted,
+        Self::Fatal,
+        Self::Other,
+    ];
+
+    pub fn of(error: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        #[cfg(unix)]
+        if let Some(code) = error.raw_os_error() {
+            match code {
+                libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM => {
+                    return Self::ResourceExhausted
+                }
+                libc::EBADF | libc::EINVAL | libc::ENOTSOCK | libc::EFAULT => return Self::Fatal,
+                // Network errors of the pending connection, which accept(2)
+                // says to treat like EAGAIN.
+                libc::EPROTO
+                | libc::ENETDOWN
+                | libc::ENETUNREACH
+                | libc::EHOSTDOWN
+                | libc::EHOSTUNREACH
+                | libc::ENOPROTOOPT
+                | libc::EOPNOTSUPP => return Self::Connection,
+                _ => {}
+            }
+        }
+
+        match error.kind() {
+            ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut => Self::Connection,
+            ErrorKind::OutOfMemory => Self::ResourceExhausted,
+            ErrorKind::InvalidInput => Self::Fatal,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for AcceptErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connection => write!(f, "connection"),
+            Self::ResourceExhausted => write!(f, "resource exhausted"),
+            Self::Fatal => write!(f, "fatal"),
+            Self::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Code of an [`HttpStatus::Custom`]. Only [`HttpStatus::from_code`]
+/// builds one, so it is always in range and never a code with a variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomCode(u16);
+
+impl CustomCode {
+    pub fn get(self) -> u16 {
+        self.0
+    }
+}
+
+// Defines `HttpStatus` together with its code and reason phrase tables so
+// that the three can never disagree.
+macro_rules! http_status {
+    ($($variant:ident = $code:literal, $text:literal;)+) => {
+        /// Response status codes from the IANA HTTP Status Code Registry.
+        /// Codes without a variant can be sent through `Custom`, built with
+        /// [`from_code`](Self::from_code).
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum HttpStatus {
+            $($variant,)+
+            /// Any other code from 100 to 599, sent without a reason phrase.
+            Custom(CustomCode),
+        }
+
+        impl HttpStatus {
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(Self::$variant => $code,)+
+                    Self::Custom(code) => code.get(),
+                }
+            }
+
+            pub fn text(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $text,)+
+                    Self::Custom(_) => "",
+                }
+            }
+
+            /// Maps a numeric code to its variant, falling back to `Custom`.
+            /// `None` outside 100..=599, the range RFC 9110 defines.
+            pub fn from_code(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$variant),)+
+                    100..=599 => Some(Self::Custom(CustomCode(code))),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+http_status! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
 
 impl ContentType {
@@ -45,34 +360,54 @@ impl ContentType {
 }
 
 impl HttpStatus {
-    pub fn code(&self) -> u16 {
-        *self as u16
+    // Names used before RFC 9110.
+    #[deprecated(note = "renamed to `ContentTooLarge` in RFC 9110")]
+    #[allow(non_upper_case_globals)]
+    pub const PayloadTooLarge: Self = Self::ContentTooLarge;
+
+    #[deprecated(note = "renamed to `UnprocessableContent` in RFC 9110")]
+    #[allow(non_upper_case_globals)]
+    pub const UnprocessableEntity: Self = Self::UnprocessableContent;
+
+    /// 1xx, 204 and 304 responses never carry a body.
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.code(), 100..=199 | 204 | 304)
     }
 
-    pub fn as_bytes(&self) -> &'static [u8] {
-        match self {
-            Self::Ok => b"200",
-            Self::Created => b"201",
-            Self::BadRequest => b"400",
-            Self::NotFound => b"404",
-            Self::PayloadTooLarge => b"413",
-            _ => b"500",
-        }
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
     }
 
-    pub fn text(&self) -> &'static str {
-        match self {
-            Self::Ok => "OK",
-            Self::Created => "Created",
-            Self::Accepted => "Accepted",
-            Self::NoContent => "No Content",
-            Self::BadRequest => "Bad Request",
-            Self::Forbidden => "Forbidden",
-            Self::NotFound => "Not Found",
-            Self::PayloadTooLarge => "Payload Too Large",
-            Self::InternalServerError => "Internal Server Error",
-            Self::NotImplemented => "Not Implemented",
-        }
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+
+    /// The three ASCII digits of the status line, rendered from `code()`.
+    pub fn as_bytes(&self) -> [u8; 3] {
+        let code = self.code();
+        [
+            b'0' + (code / 100) as u8,
+            b'0' + (code / 10 % 10) as u8,
+            b'0' + (code % 10) as u8,
+        ]
+    }
+}
+
+impl std::fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code(), self.text())
     }
 }
 
@@ -117,3 +452,130 @@ mod tests_contenttype {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests_method {
+    use super::*;
+
+    #[test]
+    fn test_method_roundtrip() {
+        for name in [
+            "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+        ] {
+            let method = Method::parse(name);
+            assert!(!matches!(method, Method::Extension(_)), "{}", name);
+            assert_eq!(method.as_str(), name);
+        }
+    }
+
+    #[test]
+    fn test_method_is_case_sensitive() {
+        assert_eq!(Method::parse("get"), Method::Extension("get".to_string()));
+        assert_eq!(Method::parse("PURGE").to_string(), "PURGE");
+    }
+}
+
+#[cfg(test)]
+mod tests_httpstatus {
+    use super::*;
+
+    #[test]
+    fn test_status_line_matches_code() {
+        for code in 100..600 {
+            let status = HttpStatus::from_code(code).unwrap();
+            assert_eq!(status.code(), code);
+            assert_eq!(status.as_bytes(), code.to_string().as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_known_codes() {
+        let cases = [
+            (HttpStatus::NoContent, 204, "No Content"),
+            (HttpStatus::MovedPermanently, 301, "Moved Permanently"),
+            (HttpStatus::NotModified, 304, "Not Modified"),
+            (HttpStatus::MethodNotAllowed, 405, "Method Not Allowed"),
+            (HttpStatus::ContentTooLarge, 413, "Content Too Large"),
+            (
+                HttpStatus::UnprocessableContent,
+                422,
+                "Unprocessable Content",
+            ),
+            (HttpStatus::TooManyRequests, 429, "Too Many Requests"),
+            (HttpStatus::GatewayTimeout, 504, "Gateway Timeout"),
+        ];
+
+        for (status, code, text) in cases {
+            assert_eq!(status.code(), code);
+            assert_eq!(status.text(), text);
+            assert_eq!(HttpStatus::from_code(code), Some(status));
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_pre_rfc_9110_names() {
+        assert_eq!(HttpStatus::PayloadTooLarge, HttpStatus::ContentTooLarge);
+        assert_eq!(HttpStatus::UnprocessableEntity.code(), 422);
+        assert_eq!(&HttpStatus::PayloadTooLarge.as_bytes(), b"413");
+    }
+
+    #[test]
+    fn test_custom_code() {
+        let status = HttpStatus::from_code(299).unwrap();
+
+        assert!(matches!(status, HttpStatus::Custom(code) if code.get() == 299));
+        assert_eq!(status.text(), "");
+        assert_eq!(&status.as_bytes(), b"299");
+        assert!(status.is_success());
+
+        // Known codes get their variant, so they compare equal to it.
+        assert_eq!(HttpStatus::from_code(200), Some(HttpStatus::Ok));
+        for code in [0, 42, 99, 600, 1234] {
+            assert_eq!(HttpStatus::from_code(code), None);
+        }
+    }
+
+    #[test]
+    fn test_body_rules() {
+        assert!(!HttpStatus::Continue.allows_body());
+        assert!(!HttpStatus::NoContent.allows_body());
+        assert!(!HttpStatus::NotModified.allows_body());
+        assert!(HttpStatus::Ok.allows_body());
+    }
+}
+
+#[cfg(test)]
+mod tests_accepterrorkind {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    #[cfg(unix)]
+    fn test_os_errors() {
+        let kind = |code| AcceptErrorKind::of(&Error::from_raw_os_error(code));
+
+        assert_eq!(kind(libc::EMFILE), AcceptErrorKind::ResourceExhausted);
+        assert_eq!(kind(libc::ENFILE), AcceptErrorKind::ResourceExhausted);
+        assert_eq!(kind(libc::ECONNABORTED), AcceptErrorKind::Connection);
+        assert_eq!(kind(libc::EPROTO), AcceptErrorKind::Connection);
+        assert_eq!(kind(libc::EBADF), AcceptErrorKind::Fatal);
+        assert_eq!(kind(libc::EPERM), AcceptErrorKind::Other);
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let kind = |kind| AcceptErrorKind::of(&Error::from(kind));
+
+        assert_eq!(
+            kind(ErrorKind::ConnectionReset),
+            AcceptErrorKind::Connection
+        );
+        assert_eq!(
+            kind(ErrorKind::OutOfMemory),
+            AcceptErrorKind::ResourceExhausted
+        );
+        assert_eq!(kind(ErrorKind::InvalidInput), AcceptErrorKind::Fatal);
+        assert_eq!(kind(ErrorKind::PermissionDenied), AcceptErrorKind::Other);
+    }
+}
@@ -1,23 +1,155 @@
-use super::structs::RequestMeta;
-use crate::core::response::Response;
-use std::net::SocketAddr;
+use super::addr::PeerAddr;
+use super::enums::{HttpStatus, Method, Timeout};
+use super::headers::HeaderMap;
+use super::structs::{ConnectionId, RequestId, RequestMeta};
+use super::uri::Uri;
+use super::websocket::{Message, WebSocketSender};
+use crate::core::response::ResponseSender;
+use tokio::sync::oneshot;
 
-#[allow(dead_code)]
+/// Answer to a lifespan event. Dropping it without answering counts as
+/// success, so applications that do not care about lifespan can ignore it.
+#[derive(Debug)]
+pub struct LifespanAck {
+    tx: oneshot::Sender<Result<(), String>>,
+}
+
+impl LifespanAck {
+    pub(crate) fn channel() -> (Self, oneshot::Receiver<Result<(), String>>) {
+        let (tx, rx) = oneshot::channel();
+        (Self { tx }, rx)
+    }
+
+    pub fn ok(self) {
+        let _ = self.tx.send(Ok(()));
+    }
+
+    /// Fails the lifespan step. A failed startup makes `run_server` return
+    /// an error without accepting any connection.
+    pub fn fail(self, reason: impl Into<String>) {
+        let _ = self.tx.send(Err(reason.into()));
+    }
+}
+
+#[allow(dead_code, clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Event {
+    // Lifespan: sent first, before any connection is accepted. The server
+    // waits for `ack` before it starts accepting.
+    Startup {
+        ack: LifespanAck,
+    },
     RequestStart {
-        method: String,
-        path: String,
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        client_addr: PeerAddr,
+        method: Method,
+        uri: Uri,
+        // 0 for HTTP/1.0, 1 for HTTP/1.1, 2 for HTTP/2.
         version: u8,
+        headers: HeaderMap,
+        // Body bytes that arrived together with the head.
         rest: Vec<u8>,
+        // Whether `RequestBody` events follow. With `meta.expect_continue`
+        // they only do after `resp_tx.send_continue()`.
+        more_body: bool,
         meta: RequestMeta,
-        resp_tx: tokio::sync::oneshot::Sender<Response>,
+        resp_tx: ResponseSender,
     },
     RequestBody {
+        connection_id: ConnectionId,
+        request_id: RequestId,
         body: Vec<u8>,
         more_body: bool,
     },
+    // The server answered with an error response and closed the connection.
+    RequestRejected {
+        connection_id: ConnectionId,
+        // Set when the application already saw the request's `RequestStart`.
+        request_id: Option<RequestId>,
+        client_addr: PeerAddr,
+        status: HttpStatus,
+        reason: String,
+    },
+    // The server gave up on a request because a timeout expired, answered
+    // with `timeout.status()` if no response had started, and closed the
+    // connection. Handlers still working on the request can stop.
+    RequestTimeout {
+        connection_id: ConnectionId,
+        // Set when the application already saw the request's `RequestStart`.
+        request_id: Option<RequestId>,
+        client_addr: PeerAddr,
+        timeout: Timeout,
+    },
+    // A client asked to switch to a WebSocket. Answer the handshake through
+    // `ws_tx`; the request's `RequestId` identifies the WebSocket from then
+    // on.
+    WebSocketConnect {
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        client_addr: PeerAddr,
+        uri: Uri,
+        headers: HeaderMap,
+        // Subprotocols offered in `Sec-WebSocket-Protocol`.
+        protocols: Vec<String>,
+        ws_tx: WebSocketSender,
+    },
+    WebSocketReceive {
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        message: Message,
+    },
+    // The WebSocket is closed. `code` is the one from the close frame
+    // either side sent first, or 1006 if the connection dropped without one.
+    WebSocketDisconnect {
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        client_addr: PeerAddr,
+        code: u16,
+        reason: String,
+    },
+    // The connection ended. On HTTP/2 it is also sent, with `request_id`
+    // set, for each stream the client resets.
     Disconnect {
-        client_addr: SocketAddr,
+        connection_id: ConnectionId,
+        // The request in flight when the connection ended, if any.
+        request_id: Option<RequestId>,
+        client_addr: PeerAddr,
     },
+    // Lifespan: the server stopped accepting and drained its connections.
+    // It is the last event sent; `run_server` returns once it is answered.
+    Shutdown {
+        ack: LifespanAck,
+    },
+}
+
+impl Event {
+    pub fn connection_id(&self) -> Option<ConnectionId> {
+        let connection_id = match self {
+            Self::RequestStart { connection_id, .. }
+            | Self::RequestBody { connection_id, .. }
+            | Self::RequestRejected { connection_id, .. }
+            | Self::RequestTimeout { connection_id, .. }
+            | Self::WebSocketConnect { connection_id, .. }
+            | Self::WebSocketReceive { connection_id, .. }
+            | Self::WebSocketDisconnect { connection_id, .. }
+            | Self::Disconnect { connection_id, .. } => connection_id,
+            Self::Startup { .. } | Self::Shutdown { .. } => return None,
+        };
+        Some(*connection_id)
+    }
+
+    pub fn request_id(&self) -> Option<RequestId> {
+        match self {
+            Self::RequestStart { request_id, .. }
+            | Self::RequestBody { request_id, .. }
+            | Self::WebSocketConnect { request_id, .. }
+            | Self::WebSocketReceive { request_id, .. }
+            | Self::WebSocketDisconnect { request_id, .. } => Some(*request_id),
+            Self::RequestRejected { request_id, .. }
+            | Self::RequestTimeout { request_id, .. }
+            | Self::Disconnect { request_id, .. } => *request_id,
+            Self::Startup { .. } | Self::Shutdown { .. } => None,
+        }
+    }
 }
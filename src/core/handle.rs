@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+
+use super::enums::AcceptErrorKind;
+
+/// Controls and observes a running server.
+///
+/// Clones share the same state. Once `shutdown` is called, the server
+/// stops accepting connections, lets in-flight requests finish within
+/// `ServerConfig::shutdown_timeout` and then returns from `run_server`.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    tx: Arc<watch::Sender<bool>>,
+    stats: Arc<ServerStats>,
+}
+
+/// Counters of a running server.
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    accepted: AtomicU64,
+    // Indexed like `AcceptErrorKind::ALL`.
+    accept_errors: [AtomicU64; 4],
+    // Connections turned away by a connection limit.
+    refused: AtomicU64,
+    // Open connections per client IP, `None` for Unix socket peers; the
+    // sum is the total.
+    open: Mutex<HashMap<Option<IpAddr>, usize>>,
+    // Signalled whenever a connection closes.
+    closed: Notify,
+}
+
+impl ServerStats {
+    /// Connections accepted so far.
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Failed `accept` calls of the given class so far.
+    pub fn accept_errors(&self, kind: AcceptErrorKind) -> u64 {
+        self.accept_errors[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Connections currently open.
+    pub fn active_connections(&self) -> usize {
+        self.open.lock().unwrap().values().sum()
+    }
+
+    /// Connections currently open from `ip`.
+    pub fn connections_from(&self, ip: IpAddr) -> usize {
+        self.open
+            .lock()
+            .unwrap()
+            .get(&Some(ip))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Connections turned away by `max_connections` or
+    /// `max_connections_per_ip` so far.
+    pub fn refused_connections(&self) -> u64 {
+        self.refused.load(Ordering::Relaxed)
+    }
+
+    /// Counts a connection from `ip` as open unless `cap` connections from
+    /// it already are. Peers without an IP are never capped.
+    pub(crate) fn try_open(&self, ip: Option<IpAddr>, cap: Option<usize>) -> bool {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(ip).or_insert(0);
+        if ip.is_some() && cap.is_some_and(|cap| *count >= cap) {
+            if *count == 0 {
+                open.remove(&ip);
+            }
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Like `try_open`, but waits for a connection from `ip` to close
+    /// while the cap is reached.
+    pub(crate) async fn open(&self, ip: Option<IpAddr>, cap: Option<usize>) {
+        loop {
+            // Registered before checking so a close in between is not missed.
+            let closed = self.closed.notified();
+            tokio::pin!(closed);
+            closed.as_mut().enable();
+
+            if self.try_open(ip, cap) {
+                return;
+            }
+            closed.await;
+        }
+    }
+
+    pub(crate) fn close(&self, ip: Option<IpAddr>) {
+        let mut open = self.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&ip);
+            }
+        }
+        drop(open);
+        self.closed.notify_waiters();
+    }
+
+    pub(crate) fn record_refused(&self) {
+        self.refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the error and returns the new count for its class.
+    pub(crate) fn record_accept_error(&self, kind: AcceptErrorKind) -> u64 {
+        self.accept_errors[kind as usize].fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl ServerHandle {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(false);
+        Self {
+            tx: Arc::new(tx),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once `shutdown` has been called, immediately if it already
+    /// was.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender lives in `self`, so this cannot fail.
+        let _ = rx.wait_for(|shutdown| *shutdown).await;
+    }
+}
+
+impl Default for ServerHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_clones_share_the_signal() {
+        let handle = ServerHandle::new();
+        let clone = handle.clone();
+        assert!(!clone.is_shutdown());
+
+        let waiter = tokio::spawn(async move { clone.wait().await });
+        handle.shutdown();
+        waiter.await.unwrap();
+
+        assert!(handle.is_shutdown());
+        // Waiting after the fact returns at once.
+        handle.wait().await;
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_counts() {
+        let stats = ServerStats::default();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(stats.try_open(Some(a), Some(2)));
+        assert!(stats.try_open(Some(a), Some(2)));
+        assert!(!stats.try_open(Some(a), Some(2)));
+        assert!(stats.try_open(Some(b), Some(2)));
+        assert_eq!(stats.connections_from(a), 2);
+        assert_eq!(stats.active_connections(), 3);
+
+        stats.close(Some(a));
+        stats.close(Some(b));
+        assert_eq!(stats.connections_from(a), 1);
+        assert_eq!(stats.connections_from(b), 0);
+        assert_eq!(stats.active_connections(), 1);
+
+        // Unix socket peers only count towards the total.
+        assert!(stats.try_open(None, Some(1)));
+        assert!(stats.try_open(None, Some(1)));
+        assert_eq!(stats.active_connections(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_open_waits_for_a_close() {
+        let stats = Arc::new(ServerStats::default());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(stats.try_open(Some(ip), Some(1)));
+
+        let waiter = tokio::spawn({
+            let stats = Arc::clone(&stats);
+            async move { stats.open(Some(ip), Some(1)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        stats.close(Some(ip));
+        waiter.await.unwrap();
+        assert_eq!(stats.connections_from(ip), 1);
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    InvalidName(String),
+    // Values may not contain CR, LF or NUL, which would allow injecting
+    // extra headers or splitting the response.
+    InvalidValue(String),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "invalid header name {:?}", name),
+            Self::InvalidValue(name) => write!(f, "invalid value for header {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// Ordered, multi-valued header collection with case-insensitive lookup.
+///
+/// Names keep the casing they were inserted with; values are raw bytes
+/// since HTTP/1.1 allows non-UTF-8 `obs-text` in field values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies the headers of a parsed request. `httparse` has already
+    /// validated them, so no further checks are done.
+    pub fn from_httparse(headers: &[httparse::Header<'_>]) -> Self {
+        Self {
+            entries: headers
+                .iter()
+                .map(|h| (h.name.to_string(), h.value.to_vec()))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    /// First value of `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// First value of `name`, if it is valid UTF-8.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// All values of `name` in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Whether any value of `name` contains `token` in its comma-separated
+    /// list, compared case-insensitively (e.g. `Connection: keep-alive, close`).
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).any(|value| {
+            value
+                .split(|&b| b == b',')
+                .any(|t| t.trim_ascii().eq_ignore_ascii_case(token.as_bytes()))
+        })
+    }
+
+    /// Sets `name` to a single value, replacing every existing value
+    /// regardless of case. The header keeps the position of its first
+    /// occurrence.
+    pub fn insert(&mut self, name: &str, value: impl AsRef<[u8]>) -> Result<(), HeaderError> {
+        let value = value.as_ref();
+        validate(name, value)?;
+
+        match self
+            .entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some(index) => {
+                self.entries[index] = (name.to_string(), value.to_vec());
+                let mut i = index + 1;
+                while i < self.entries.len() {
+                    if self.entries[i].0.eq_ignore_ascii_case(name) {
+                        self.entries.remove(i);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            None => self.entries.push((name.to_string(), value.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Adds another value for `name`, keeping existing ones.
+    pub fn append(&mut self, name: &str, value: impl AsRef<[u8]>) -> Result<(), HeaderError> {
+        let value = value.as_ref();
+        validate(name, value)?;
+        self.entries.push((name.to_string(), value.to_vec()));
+        Ok(())
+    }
+
+    /// Removes every value of `name`, returning whether any existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.entries.len() != before
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_slice()))
+    }
+
+    /// Serializes every header as `Name: value\r\n`.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        for (name, value) in &self.entries {
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value);
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn validate(name: &str, value: &[u8]) -> Result<(), HeaderError> {
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return Err(HeaderError::InvalidName(name.to_string()));
+    }
+    if value.iter().any(|&b| b == b'\r' || b == b'\n' || b == 0) {
+        return Err(HeaderError::InvalidValue(name.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_insensitive_lookup() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain").unwrap();
+
+        assert_eq!(headers.get("content-type"), Some(&b"text/plain"[..]));
+        assert_eq!(headers.get_str("CONTENT-TYPE"), Some("text/plain"));
+        assert!(headers.contains("Content-type"));
+        assert!(headers.get("Content-Length").is_none());
+    }
+
+    #[test]
+    fn test_insert_replaces_case_variants() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-First", "1").unwrap();
+        headers.append("content-length", "10").unwrap();
+        headers.append("CONTENT-LENGTH", "20").unwrap();
+        headers.insert("Content-Length", "3").unwrap();
+
+        let all: Vec<_> = headers.iter().collect();
+        assert_eq!(
+            all,
+            vec![("X-First", &b"1"[..]), ("Content-Length", &b"3"[..])]
+        );
+    }
+
+    #[test]
+    fn test_multiple_values_keep_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1").unwrap();
+        headers.append("Server", "Aegis").unwrap();
+        headers.append("Set-Cookie", "b=2").unwrap();
+
+        let cookies: Vec<_> = headers.get_all("set-cookie").collect();
+        assert_eq!(cookies, vec![&b"a=1"[..], &b"b=2"[..]]);
+
+        let mut raw = Vec::new();
+        headers.write_to(&mut raw);
+        assert_eq!(
+            raw,
+            b"Set-Cookie: a=1\r\nServer: Aegis\r\nSet-Cookie: b=2\r\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_injection() {
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(
+            headers.insert("X-Evil", "a\r\nSet-Cookie: b"),
+            Err(HeaderError::InvalidValue("X-Evil".to_string()))
+        );
+        assert!(headers.append("X-Evil", "a\nb").is_err());
+        assert!(headers.insert("Bad Name", "v").is_err());
+        assert!(headers.insert("Bad:Name", "v").is_err());
+        assert!(headers.insert("", "v").is_err());
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_has_token_and_remove() {
+        let mut headers = HeaderMap::new();
+        headers.append("Connection", "Upgrade").unwrap();
+        headers.append("connection", "keep-alive, Close").unwrap();
+
+        assert!(headers.has_token("Connection", "close"));
+        assert!(headers.has_token("Connection", "upgrade"));
+        assert!(!headers.has_token("Connection", "keep"));
+
+        assert!(headers.remove("CONNECTION"));
+        assert!(!headers.remove("Connection"));
+        assert_eq!(headers.len(), 0);
+    }
+
+    #[test]
+    fn test_from_httparse() {
+        let raw = [
+            httparse::Header {
+                name: "Host",
+                value: b"example.com",
+            },
+            httparse::Header {
+                name: "Cookie",
+                value: b"a=1",
+            },
+            httparse::Header {
+                name: "cookie",
+                value: b"b=2",
+            },
+        ];
+        let headers = HeaderMap::from_httparse(&raw);
+
+        assert_eq!(headers.get_str("host"), Some("example.com"));
+        assert_eq!(headers.get_all("Cookie").count(), 2);
+    }
+}
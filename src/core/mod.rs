@@ -1,4 +1,10 @@
+pub mod addr;
 pub mod enums;
 pub mod events;
+pub mod handle;
+pub mod headers;
 pub mod response;
+pub mod sse;
 pub mod structs;
+pub mod uri;
+pub mod websocket;
@@ -1,65 +1,123 @@
 use crate::core::enums::HttpStatus;
-use std::collections::HashMap;
+use crate::core::headers::{HeaderError, HeaderMap};
+use crate::core::sse::{self, PendingStream, SseStream};
+use std::fmt;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+// Messages a single response may be split into before the server
+// applies backpressure to the sender.
+const RESPONSE_CHANNEL_CAPACITY: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct Response {
     pub status: HttpStatus,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
     pub body: Vec<u8>,
     pub version: String, // HTTP/1.1
+    // Set by `event_stream`; written by the service dispatcher.
+    pub(crate) stream: Option<PendingStream>,
 }
 
 impl Response {
     pub fn new(status: HttpStatus) -> Self {
-        let mut headers = HashMap::new();
-        headers.insert("Server".to_string(), "Aegis/0.1".to_string());
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert("Server", "Aegis/0.1");
 
         Self {
             status,
             headers,
             body: Vec::new(),
             version: "HTTP/1.1".to_string(),
+            stream: None,
         }
     }
 
     // Builder-pattern
+    /// Sets a header, replacing any existing value regardless of case.
+    /// Invalid names or values are dropped with a warning; use
+    /// `try_header` to handle the error instead.
     pub fn header(mut self, name: &str, value: &str) -> Self {
-        self.headers.insert(name.to_string(), value.to_string());
+        if let Err(e) = self.headers.insert(name, value) {
+            warn!("Dropping response header: {}", e);
+        }
         self
     }
 
+    /// Adds another value for a header, e.g. a second `Set-Cookie`.
+    pub fn append_header(mut self, name: &str, value: &str) -> Self {
+        if let Err(e) = self.headers.append(name, value) {
+            warn!("Dropping response header: {}", e);
+        }
+        self
+    }
+
+    pub fn try_header(mut self, name: &str, value: &str) -> Result<Self, HeaderError> {
+        self.headers.insert(name, value)?;
+        Ok(self)
+    }
+
     pub fn body(mut self, body: Vec<u8>) -> Self {
-        self.headers
-            .insert("Content-Length".to_string(), body.len().to_string());
+        let _ = self
+            .headers
+            .insert("Content-Length", body.len().to_string());
         self.body = body;
         self
     }
 
+    /// Turns the response into a `text/event-stream` whose body is written
+    /// from `stream` once a handler returns it. Any body set before is
+    /// ignored. With the raw event API, use [`SseStream::serve`] instead.
+    pub fn event_stream(self, stream: SseStream) -> Self {
+        let mut response = sse::event_stream_head(self);
+        response.body.clear();
+        response.stream = Some(PendingStream::new(stream));
+        response
+    }
+
+    #[cfg(feature = "service")]
+    pub(crate) fn take_event_stream(&mut self) -> Option<SseStream> {
+        self.stream.take()?.take()
+    }
+
+    /// Case-insensitive header lookup.
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers.contains(name)
+    }
+
+    /// Whether the response asks for the connection to be closed.
+    pub fn closes_connection(&self) -> bool {
+        self.headers.has_token("Connection", "close")
+    }
+
     pub fn build(self) -> Vec<u8> {
+        let mut res = self.build_head();
+
+        // 4. Body
+        res.extend_from_slice(&self.body);
+
+        res
+    }
+
+    /// Serializes the status line and headers only, up to and including
+    /// the empty line that precedes the body.
+    pub fn build_head(&self) -> Vec<u8> {
         let mut res = Vec::with_capacity(256 + self.body.len());
 
         // 1. Status line
         res.extend_from_slice(self.version.as_bytes()); // "HTTP/1.1"
         res.push(b' ');
-        res.extend_from_slice(self.status.as_bytes()); // "Status as number"
+        res.extend_from_slice(&self.status.as_bytes()); // "Status as number"
         res.push(b' ');
         res.extend_from_slice(self.status.text().as_bytes()); // "Status as text"
         res.extend_from_slice(b"\r\n");
 
         // 2. Headers
-        for (name, value) in &self.headers {
-            res.extend_from_slice(name.as_bytes());
-            res.extend_from_slice(b": ");
-            res.extend_from_slice(value.as_bytes());
-            res.extend_from_slice(b"\r\n");
-        }
+        self.headers.write_to(&mut res);
 
         // 3. An empty string before the body
         res.extend_from_slice(b"\r\n");
 
-        // 4. Body
-        res.extend_from_slice(&self.body);
-
         res
     }
 
@@ -72,6 +130,169 @@ impl Response {
     pub fn bad_request() -> Self {
         Self::new(HttpStatus::BadRequest)
     }
+
+    /// Plain-text response whose body is the status reason phrase.
+    pub fn error(status: HttpStatus) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain")
+            .body(status.text().as_bytes().to_vec())
+    }
+}
+
+/// One part of a response sent from the application to the server.
+#[derive(Debug)]
+pub enum ResponseMessage {
+    /// Interim `100 Continue`, asking a client that sent
+    /// `Expect: 100-continue` for the body. Dropped when not expected.
+    Continue,
+    /// Status line and headers. The body of the carried `Response` is ignored.
+    Start(Response),
+    Body {
+        data: Vec<u8>,
+        more_body: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseError {
+    /// The connection is gone and nothing more can be sent.
+    Closed,
+    AlreadyStarted,
+    NotStarted,
+    AlreadyFinished,
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "connection closed"),
+            Self::AlreadyStarted => write!(f, "response already started"),
+            Self::NotStarted => write!(f, "response body sent before start"),
+            Self::AlreadyFinished => write!(f, "response already finished"),
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+/// Per-request handle the application uses to answer a request.
+///
+/// Either send a complete `Response` at once with [`ResponseSender::send`],
+/// or stream it: [`ResponseSender::start`] followed by one or more
+/// [`ResponseSender::body`] calls, the last one with `more_body == false`.
+/// Streamed responses without a `Content-Length` header are written with
+/// `Transfer-Encoding: chunked`.
+///
+/// When the request carries `Expect: 100-continue` (see
+/// `RequestMeta::expect_continue`), the client holds the body back until
+/// [`ResponseSender::send_continue`] is called. Answering with a final
+/// response instead, e.g. `417` or `401`, skips the body and closes the
+/// connection afterwards.
+#[derive(Debug)]
+pub struct ResponseSender {
+    tx: mpsc::Sender<ResponseMessage>,
+    started: bool,
+    finished: bool,
+}
+
+impl ResponseSender {
+    pub fn channel() -> (Self, mpsc::Receiver<ResponseMessage>) {
+        let (tx, rx) = mpsc::channel(RESPONSE_CHANNEL_CAPACITY);
+        let sender = Self {
+            tx,
+            started: false,
+            finished: false,
+        };
+        (sender, rx)
+    }
+
+    /// Sends a complete response. Gives the response back if the
+    /// connection is gone, the response was already started or the channel
+    /// is full.
+    pub fn send(self, mut response: Response) -> Result<(), Response> {
+        if self.started {
+            return Err(response);
+        }
+
+        let data = std::mem::take(&mut response.body);
+        if !response.has_header("Content-Length") && response.status.allows_body() {
+            response = response.header("Content-Length", &data.len().to_string());
+        }
+
+        // Both messages or neither, so the connection never waits for a
+        // body that was dropped. Queued `Continue`s can leave the channel
+        // too full for two.
+        let Ok(mut permits) = self.tx.try_reserve_many(2) else {
+            response.body = data;
+            return Err(response);
+        };
+        if let Some(permit) = permits.next() {
+            permit.send(ResponseMessage::Start(response));
+        }
+        if let Some(permit) = permits.next() {
+            permit.send(ResponseMessage::Body {
+                data,
+                more_body: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Tells a client waiting on `Expect: 100-continue` to send the body.
+    /// Does nothing for other requests or once the response has started.
+    pub async fn send_continue(&self) -> Result<(), ResponseError> {
+        if self.started {
+            return Ok(());
+        }
+        self.tx
+            .send(ResponseMessage::Continue)
+            .await
+            .map_err(|_| ResponseError::Closed)
+    }
+
+    /// A handle that can send `Continue` without keeping the response
+    /// channel open.
+    #[cfg(feature = "service")]
+    pub(crate) fn downgrade(&self) -> mpsc::WeakSender<ResponseMessage> {
+        self.tx.downgrade()
+    }
+
+    /// Sends the status line and headers of a streamed response.
+    pub async fn start(&mut self, head: Response) -> Result<(), ResponseError> {
+        if self.started {
+            return Err(ResponseError::AlreadyStarted);
+        }
+        self.started = true;
+        self.tx
+            .send(ResponseMessage::Start(head))
+            .await
+            .map_err(|_| ResponseError::Closed)
+    }
+
+    /// Sends a piece of the body; `more_body == false` ends the response.
+    pub async fn body(&mut self, data: Vec<u8>, more_body: bool) -> Result<(), ResponseError> {
+        if !self.started {
+            return Err(ResponseError::NotStarted);
+        }
+        if self.finished {
+            return Err(ResponseError::AlreadyFinished);
+        }
+        self.finished = !more_body;
+        self.tx
+            .send(ResponseMessage::Body { data, more_body })
+            .await
+            .map_err(|_| ResponseError::Closed)
+    }
+
+    /// Whether the connection that would receive the response is gone.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Waits until the connection that would receive the response is gone.
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
 }
 
 #[cfg(test)]
@@ -152,6 +373,140 @@ mod tests {
         assert!(raw.contains("Server: Aegis/0.1\r\n"));
     }
 
+    #[test]
+    fn test_header_order_and_duplicates() {
+        let raw = Response::ok()
+            .header("content-type", "text/plain")
+            .append_header("Set-Cookie", "a=1")
+            .append_header("Set-Cookie", "b=2")
+            .header("Content-Type", "application/json")
+            .body(b"{}".to_vec())
+            .build();
+        let s = String::from_utf8(raw).unwrap();
+
+        assert!(s.starts_with(
+            "HTTP/1.1 200 OK\r\n\
+             Server: Aegis/0.1\r\n\
+             Content-Type: application/json\r\n\
+             Set-Cookie: a=1\r\n\
+             Set-Cookie: b=2\r\n\
+             Content-Length: 2\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_header_injection_is_dropped() {
+        let response = Response::ok().header("X-Evil", "a\r\nSet-Cookie: pwned=1");
+        assert!(!response.has_header("X-Evil"));
+
+        assert!(Response::ok().try_header("X-Evil", "a\nb").is_err());
+    }
+
+    #[test]
+    fn test_closes_connection() {
+        assert!(!Response::ok().closes_connection());
+        assert!(Response::ok()
+            .header("connection", "close")
+            .closes_connection());
+    }
+
+    #[test]
+    fn test_error_response() {
+        let raw = String::from_utf8(Response::error(HttpStatus::ContentTooLarge).build()).unwrap();
+
+        assert!(raw.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(raw.contains("Content-Type: text/plain\r\n"));
+        assert!(raw.ends_with("\r\n\r\nContent Too Large"));
+    }
+
+    #[test]
+    fn test_build_head_has_no_body() {
+        let raw = Response::ok().body(b"abc".to_vec()).build_head();
+
+        assert!(raw.ends_with(b"\r\n\r\n"));
+        assert!(!raw.ends_with(b"abc"));
+    }
+
+    #[tokio::test]
+    async fn test_sender_send_splits_response() {
+        let (sender, mut rx) = ResponseSender::channel();
+        sender.send(Response::ok().body(b"hi".to_vec())).unwrap();
+
+        match rx.recv().await.unwrap() {
+            ResponseMessage::Start(head) => assert!(head.body.is_empty()),
+            other => panic!("unexpected {:?}", other),
+        }
+        match rx.recv().await.unwrap() {
+            ResponseMessage::Body { data, more_body } => {
+                assert_eq!(data, b"hi");
+                assert!(!more_body);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sender_send_adds_content_length() {
+        let (sender, mut rx) = ResponseSender::channel();
+        sender.send(Response::ok()).unwrap();
+
+        let Some(ResponseMessage::Start(head)) = rx.recv().await else {
+            panic!("expected start");
+        };
+        assert_eq!(head.headers.get_str("Content-Length"), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn test_sender_streaming_order() {
+        let (mut sender, _rx) = ResponseSender::channel();
+
+        assert_eq!(
+            sender.body(b"x".to_vec(), true).await,
+            Err(ResponseError::NotStarted)
+        );
+        sender.start(Response::ok()).await.unwrap();
+        assert_eq!(
+            sender.start(Response::ok()).await,
+            Err(ResponseError::AlreadyStarted)
+        );
+        sender.body(b"x".to_vec(), false).await.unwrap();
+        assert_eq!(
+            sender.body(b"y".to_vec(), false).await,
+            Err(ResponseError::AlreadyFinished)
+        );
+    }
+
+    #[test]
+    fn test_sender_closed() {
+        let (sender, rx) = ResponseSender::channel();
+        drop(rx);
+
+        let response = sender.send(Response::ok().body(b"lost".to_vec()));
+        assert_eq!(response.unwrap_err().body, b"lost");
+    }
+
+    #[tokio::test]
+    async fn test_send_after_continues_is_all_or_nothing() {
+        let (sender, mut rx) = ResponseSender::channel();
+        for _ in 0..RESPONSE_CHANNEL_CAPACITY - 1 {
+            sender.send_continue().await.unwrap();
+        }
+
+        // Room for the head but not the body: nothing is sent.
+        let response = sender.send(Response::ok().body(b"kept".to_vec()));
+        assert_eq!(response.unwrap_err().body, b"kept");
+        for _ in 0..RESPONSE_CHANNEL_CAPACITY - 1 {
+            assert!(matches!(rx.recv().await, Some(ResponseMessage::Continue)));
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_status_line_no_content() {
+        let raw = String::from_utf8(Response::new(HttpStatus::NoContent).build()).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 204 No Content\r\n"));
+    }
+
     #[test]
     fn test_status_line_exact() {
         let raw = Response::not_found().build();
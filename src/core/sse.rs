@@ -0,0 +1,377 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, warn};
+
+use crate::core::headers::HeaderMap;
+use crate::core::response::{Response, ResponseError, ResponseSender};
+
+// Events queued for one stream before the sender has to wait.
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// How often a comment is sent on an otherwise idle stream, unless
+/// changed with [`SseStream::heartbeat`].
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// One event of a `text/event-stream` response.
+///
+/// ```
+/// # use aegis::core::sse::SseEvent;
+/// let event = SseEvent::new("42").event("progress").id("7");
+/// assert_eq!(event.encode(), b"event: progress\nid: 7\ndata: 42\n\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: Option<String>,
+}
+
+impl SseEvent {
+    /// An unnamed event, which browsers dispatch as `message`. Each line of
+    /// `data` becomes its own `data:` field.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: Some(data.into()),
+            ..Self::default()
+        }
+    }
+
+    // Builder-pattern
+    /// Names the event. Names containing a line break are dropped with a
+    /// warning, as they would start a new field.
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = single_line("event", name);
+        self
+    }
+
+    /// Sets the ID the client reports back in `Last-Event-ID` when it
+    /// reconnects. IDs containing a line break or NUL are dropped with a
+    /// warning.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = single_line("id", id).filter(|id| !id.contains('\0'));
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting.
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = Some(delay);
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Serializes the event, including the empty line that ends it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(event) = &self.event {
+            write_field(&mut out, "event", event);
+        }
+        if let Some(id) = &self.id {
+            write_field(&mut out, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            write_field(&mut out, "retry", &retry.as_millis().to_string());
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                write_field(&mut out, "data", line);
+            }
+        }
+        out.push(b'\n');
+        out
+    }
+}
+
+/// Serializes a comment, which clients ignore.
+pub fn encode_comment(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for line in lines(text) {
+        out.push(b':');
+        if !line.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(line.as_bytes());
+        }
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+    out
+}
+
+/// The `Last-Event-ID` a reconnecting client sent, if any.
+pub fn last_event_id(headers: &HeaderMap) -> Option<&str> {
+    headers.get_str("Last-Event-ID")
+}
+
+/// Creates the two halves of an event stream: the application sends
+/// events through the [`SseSender`] while the [`SseStream`] writes them to
+/// the response.
+pub fn channel() -> (SseSender, SseStream) {
+    let (tx, rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+    let stream = SseStream {
+        rx,
+        heartbeat: Some(DEFAULT_HEARTBEAT),
+    };
+    (SseSender { tx }, stream)
+}
+
+/// Sending half of an event stream.
+///
+/// Dropping every clone of the sender ends the response. Once the client
+/// disconnects, sending fails with `ResponseError::Closed` and
+/// [`closed`](Self::closed) returns.
+#[derive(Debug, Clone)]
+pub struct SseSender {
+    tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl SseSender {
+    pub async fn send(&self, event: SseEvent) -> Result<(), ResponseError> {
+        self.send_raw(event.encode()).await
+    }
+
+    pub async fn comment(&self, text: &str) -> Result<(), ResponseError> {
+        self.send_raw(encode_comment(text)).await
+    }
+
+    /// Waits until the client is gone or the stream was ended.
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    async fn send_raw(&self, data: Vec<u8>) -> Result<(), ResponseError> {
+        self.tx.send(data).await.map_err(|_| ResponseError::Closed)
+    }
+}
+
+/// Receiving half of an event stream, written out as a streamed response
+/// by [`serve`](Self::serve) or, in a handler, returned with
+/// [`Response::event_stream`].
+pub struct SseStream {
+    rx: mpsc::Receiver<Vec<u8>>,
+    heartbeat: Option<Duration>,
+}
+
+impl fmt::Debug for SseStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseStream")
+            .field("heartbeat", &self.heartbeat)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SseStream {
+    /// Interval of the comments sent while no events are, which keep
+    /// proxies from timing the connection out and reveal a client that
+    /// went away. `None` disables them.
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Starts the response with `head`, marked as `text/event-stream`, and
+    /// writes events until the sender is dropped or the client disconnects.
+    pub async fn serve(
+        mut self,
+        mut resp_tx: ResponseSender,
+        head: Response,
+    ) -> Result<(), ResponseError> {
+        let mut head = event_stream_head(head);
+        head.body.clear();
+        resp_tx.start(head).await?;
+
+        let mut next_heartbeat = self.heartbeat.map(|interval| Instant::now() + interval);
+        loop {
+            tokio::select! {
+                // 1. Forwarding events, ending the response with the sender.
+                data = self.rx.recv() => {
+                    let Some(data) = data else {
+                        return resp_tx.body(Vec::new(), false).await;
+                    };
+                    resp_tx.body(data, true).await?;
+                }
+                // 2. Keeping an idle stream alive.
+                _ = async {
+                    match next_heartbeat {
+                        Some(at) => sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    resp_tx.body(encode_comment(""), true).await?;
+                }
+                // 3. The client going away, which drops the receiver so the
+                //    sender notices.
+                _ = resp_tx.closed() => {
+                    debug!("Event stream closed by the client");
+                    return Err(ResponseError::Closed);
+                }
+            }
+            next_heartbeat = self.heartbeat.map(|interval| Instant::now() + interval);
+        }
+    }
+}
+
+/// An [`SseStream`] waiting in a `Response` for the server to write it.
+/// Clones share the stream; only the first one taken is served.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "service"), allow(dead_code))]
+pub(crate) struct PendingStream(Arc<Mutex<Option<SseStream>>>);
+
+impl PendingStream {
+    pub(crate) fn new(stream: SseStream) -> Self {
+        Self(Arc::new(Mutex::new(Some(stream))))
+    }
+
+    #[cfg(feature = "service")]
+    pub(crate) fn take(&self) -> Option<SseStream> {
+        self.0.lock().ok()?.take()
+    }
+}
+
+/// Sets the headers every event stream needs. A `Content-Length` would end
+/// the response early, so it is removed.
+pub(crate) fn event_stream_head(mut head: Response) -> Response {
+    head.headers.remove("Content-Length");
+    head.header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+}
+
+fn single_line(field: &str, value: &str) -> Option<String> {
+    if value.contains(['\r', '\n']) {
+        warn!("Dropping SSE {} containing a line break", field);
+        return None;
+    }
+    Some(value.to_string())
+}
+
+/// Splits on CRLF, CR or LF, the line endings the event stream format
+/// accepts.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n')
+        .flat_map(|line| line.strip_suffix('\r').unwrap_or(line).split('\r'))
+}
+
+fn write_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::response::ResponseMessage;
+
+    async fn next_body(rx: &mut mpsc::Receiver<ResponseMessage>) -> (Vec<u8>, bool) {
+        match rx.recv().await {
+            Some(ResponseMessage::Body { data, more_body }) => (data, more_body),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode_fields() {
+        let event = SseEvent::new("a\nb\r\nc\rd")
+            .event("update")
+            .id("9")
+            .retry(Duration::from_secs(3));
+
+        assert_eq!(
+            event.encode(),
+            b"event: update\nid: 9\nretry: 3000\ndata: a\ndata: b\ndata: c\ndata: d\n\n"
+        );
+        assert_eq!(SseEvent::new("").encode(), b"data: \n\n");
+        assert_eq!(
+            SseEvent::default()
+                .retry(Duration::from_millis(500))
+                .encode(),
+            b"retry: 500\n\n"
+        );
+    }
+
+    #[test]
+    fn test_field_injection_is_dropped() {
+        let event = SseEvent::new("x").event("a\ndata: evil").id("1\r2");
+        assert_eq!(event.encode(), b"data: x\n\n");
+
+        assert_eq!(SseEvent::new("x").id("a\0b").encode(), b"data: x\n\n");
+    }
+
+    #[test]
+    fn test_encode_comment() {
+        assert_eq!(encode_comment(""), b":\n\n");
+        assert_eq!(encode_comment("a\nb"), b": a\n: b\n\n");
+    }
+
+    #[test]
+    fn test_last_event_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(last_event_id(&headers), None);
+
+        headers.append("last-event-id", "17").unwrap();
+        assert_eq!(last_event_id(&headers), Some("17"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_streams_events_until_sender_drops() {
+        let (resp_tx, mut rx) = ResponseSender::channel();
+        let (sender, stream) = channel();
+        let serving = tokio::spawn(stream.serve(resp_tx, Response::ok().body(b"x".to_vec())));
+
+        let Some(ResponseMessage::Start(head)) = rx.recv().await else {
+            panic!("expected start");
+        };
+        assert_eq!(
+            head.headers.get_str("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert!(!head.has_header("Content-Length"));
+
+        sender.send(SseEvent::new("one")).await.unwrap();
+        assert_eq!(next_body(&mut rx).await, (b"data: one\n\n".to_vec(), true));
+
+        drop(sender);
+        assert_eq!(next_body(&mut rx).await, (Vec::new(), false));
+        assert_eq!(serving.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_serve_sends_heartbeats() {
+        let (resp_tx, mut rx) = ResponseSender::channel();
+        let (_sender, stream) = channel();
+        let stream = stream.heartbeat(Some(Duration::from_millis(20)));
+        tokio::spawn(stream.serve(resp_tx, Response::ok()));
+
+        rx.recv().await.unwrap();
+        assert_eq!(next_body(&mut rx).await, (b":\n\n".to_vec(), true));
+        assert_eq!(next_body(&mut rx).await, (b":\n\n".to_vec(), true));
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_closes_sender() {
+        let (resp_tx, mut rx) = ResponseSender::channel();
+        let (sender, stream) = channel();
+        let serving = tokio::spawn(stream.serve(resp_tx, Response::ok()));
+
+        rx.recv().await.unwrap();
+        drop(rx);
+
+        sender.closed().await;
+        assert_eq!(
+            sender.send(SseEvent::new("late")).await,
+            Err(ResponseError::Closed)
+        );
+        assert_eq!(serving.await.unwrap(), Err(ResponseError::Closed));
+    }
+}
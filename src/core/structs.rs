@@ -1,20 +1,89 @@
 use dotenvy;
 use std::env;
-use std::net::SocketAddr;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use super::enums::ContentType;
+use super::addr::ServerAddr;
+use super::enums::{ContentType, LimitPolicy, ParsingMode};
 
 #[derive(Debug, Default, Clone)]
 pub struct RequestMeta {
     pub content_length: Option<usize>,
     pub content_type: Option<ContentType>,
     pub is_chunked: bool,
+    // Tokens of the `Connection` header.
+    pub connection_close: bool,
+    pub connection_keep_alive: bool,
+    // `Expect: 100-continue`: the client waits for `100 Continue` before
+    // sending the body.
+    pub expect_continue: bool,
+    // Any other expectation, which the server cannot meet.
+    pub expect_unsupported: bool,
 }
 
+/// Identifies one accepted connection for the lifetime of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u64);
+
+/// Identifies one request; unique across all connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(pub u64);
+
+/// A PEM certificate chain and its private key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct ServerConfig {
-    pub addr: SocketAddr,
+    // `host:port`, or `unix:/path` for a Unix socket.
+    pub addr: ServerAddr,
+    // Permissions of a Unix socket file, e.g. `0o660`; `None` keeps the
+    // umask default.
+    pub unix_socket_mode: Option<u32>,
     pub max_payload_size: usize,
     pub read_buffer_size: usize,
+    // Time allowed for a whole request head, counted from its first byte.
+    // Also bounds the wait for the first request on a new connection.
+    pub header_read_timeout: Duration,
+    // Longest pause between two reads of a request body.
+    pub body_read_timeout: Duration,
+    // How long an idle keep-alive connection waits for the next request.
+    pub keep_alive_timeout: Duration,
+    // Time the application has to start a response after the request head.
+    pub response_timeout: Duration,
+    // How long a graceful shutdown waits for in-flight requests.
+    pub shutdown_timeout: Duration,
+    // Open connections allowed in total and per client IP; `None` means
+    // unlimited.
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    // Applies to both limits.
+    pub connection_limit_policy: LimitPolicy,
+    // Certificate served when no SNI entry matches. TLS is off unless this
+    // or `tls_sni_certs` is set.
+    pub tls_cert: Option<CertPaths>,
+    // Certificates picked by the SNI host name; `*.example.com` matches one
+    // label.
+    pub tls_sni_certs: Vec<(String, CertPaths)>,
+    // ALPN protocols offered, most preferred first.
+    pub tls_alpn: Vec<String>,
+    pub tls_handshake_timeout: Duration,
+    // How often certificate files are checked for changes; `None` only
+    // reloads on request.
+    pub tls_reload_interval: Option<Duration>,
+    // Streams an HTTP/2 client may have open at once.
+    pub http2_max_concurrent_streams: u32,
+    // Largest WebSocket message accepted, after reassembling fragments.
+    pub websocket_max_message_size: usize,
+    // How HTTP/1.x request framing is checked.
+    pub parsing_mode: ParsingMode,
 }
 
 impl RequestMeta {
@@ -22,26 +91,163 @@ impl RequestMeta {
         Self::default()
     }
 
-    pub fn from_headers(headers: &[httparse::Header<'_>]) -> Self {
+    /// Reads the headers that shape how a request is handled, rejecting
+    /// ones that leave the end of the body ambiguous (RFC 9112, section
+    /// 6.3). `mode` decides what counts as ambiguous.
+    pub fn from_headers(
+        headers: &[httparse::Header<'_>],
+        mode: ParsingMode,
+    ) -> Result<Self, FramingError> {
         let mut meta = Self::new();
+        // Transfer codings in the order they were applied.
+        let mut codings: Vec<String> = Vec::new();
+        let mut has_transfer_encoding = false;
 
         for header in headers {
             if header.name.eq_ignore_ascii_case("content-length") {
-                meta.content_length = std::str::from_utf8(header.value)
-                    .ok()
-                    .and_then(|s| s.trim().parse().ok());
+                // A list of identical values is a duplicated header that a
+                // proxy folded into one.
+                for value in header.value.split(|&b| b == b',') {
+                    let len = parse_content_length(value.trim_ascii())
+                        .ok_or(FramingError::InvalidContentLength)?;
+                    if meta.content_length.is_some_and(|known| known != len) {
+                        return Err(FramingError::ConflictingContentLength);
+                    }
+                    meta.content_length = Some(len);
+                }
             } else if header.name.eq_ignore_ascii_case("content-type") {
                 meta.content_type = Some(ContentType::from_header_value(header.value));
-            } else if header.name.eq_ignore_ascii_case("transfer-encoding")
-                && header
+            } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+                has_transfer_encoding = true;
+                for coding in header.value.split(|&b| b == b',') {
+                    let coding = coding.trim_ascii();
+                    if !coding.is_empty() {
+                        codings.push(String::from_utf8_lossy(coding).to_ascii_lowercase());
+                    }
+                }
+            } else if header.name.eq_ignore_ascii_case("connection") {
+                for token in header.value.split(|&b| b == b',') {
+                    let token = token.trim_ascii();
+                    if token.eq_ignore_ascii_case(b"close") {
+                        meta.connection_close = true;
+                    } else if token.eq_ignore_ascii_case(b"keep-alive") {
+                        meta.connection_keep_alive = true;
+                    }
+                }
+            } else if header.name.eq_ignore_ascii_case("expect") {
+                if header
                     .value
-                    .windows(7)
-                    .any(|w| w.eq_ignore_ascii_case(b"chunked"))
-            {
-                meta.is_chunked = true;
+                    .trim_ascii()
+                    .eq_ignore_ascii_case(b"100-continue")
+                {
+                    meta.expect_continue = true;
+                } else {
+                    meta.expect_unsupported = true;
+                }
+            }
+        }
+
+        if has_transfer_encoding {
+            // CONDITION
+            // IF the body length does not come from chunked framing.
+            if codings.last().map(String::as_str) != Some("chunked") {
+                return Err(FramingError::ChunkedNotFinal);
+            }
+            let applied = &codings[..codings.len() - 1];
+            if applied.iter().any(|c| c == "chunked") {
+                return Err(FramingError::ChunkedNotFinal);
+            }
+            if let (ParsingMode::Strict, Some(coding)) = (mode, applied.first()) {
+                return Err(FramingError::UnsupportedTransferCoding(coding.clone()));
+            }
+
+            // CONDITION
+            // IF both headers claim to frame the body.
+            if meta.content_length.is_some() {
+                if mode == ParsingMode::Strict {
+                    return Err(FramingError::ContentLengthWithTransferEncoding);
+                }
+                // Whoever sent it may frame the body differently, so the
+                // connection is not reused.
+                meta.content_length = None;
+                meta.connection_close = true;
+            }
+            meta.is_chunked = true;
+        }
+        Ok(meta)
+    }
+
+    /// Whether the client expects the connection to stay open after this
+    /// request. HTTP/1.1 (`version == 1`) defaults to keep-alive, HTTP/1.0
+    /// only keeps the connection when asked to.
+    pub fn wants_keep_alive(&self, version: u8) -> bool {
+        if self.connection_close {
+            return false;
+        }
+        version >= 1 || self.connection_keep_alive
+    }
+}
+
+/// Only digits, so `+5` or `0x10` never pass as a length.
+fn parse_content_length(value: &[u8]) -> Option<usize> {
+    if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Why a request's body cannot be delimited safely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FramingError {
+    InvalidContentLength,
+    // Several `Content-Length` values that differ.
+    ConflictingContentLength,
+    ContentLengthWithTransferEncoding,
+    // A coding the server cannot decode, applied before `chunked`.
+    UnsupportedTransferCoding(String),
+    // `Transfer-Encoding` that does not end with exactly one `chunked`.
+    ChunkedNotFinal,
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidContentLength => write!(f, "invalid Content-Length"),
+            Self::ConflictingContentLength => write!(f, "conflicting Content-Length values"),
+            Self::ContentLengthWithTransferEncoding => {
+                write!(f, "both Content-Length and Transfer-Encoding")
             }
+            Self::UnsupportedTransferCoding(coding) => {
+                write!(f, "unsupported transfer coding {:?}", coding)
+            }
+            Self::ChunkedNotFinal => write!(f, "Transfer-Encoding must end with chunked"),
         }
-        meta
+    }
+}
+
+impl std::error::Error for FramingError {}
+
+impl ConnectionId {
+    pub fn next() -> Self {
+        Self(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl RequestId {
+    pub fn next() -> Self {
+        Self(NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "conn-{}", self.0)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "req-{}", self.0)
     }
 }
 
@@ -57,6 +263,13 @@ impl ServerConfig {
             .map(|s| s.parse().expect("Invalid SERVER_ADDR format"))
             .unwrap_or_else(|_| "127.0.0.1:8080".parse().unwrap());
 
+        let unix_socket_mode = env::var("UNIX_SOCKET_MODE").ok().map(|s| {
+            u32::from_str_radix(&s, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .expect("UNIX_SOCKET_MODE must be an octal file mode (e.g. 660)")
+        });
+
         let max_payload_size = env::var("MAX_PAYLOAD_SIZE")
             .map(|s| {
                 let val = s
@@ -81,11 +294,167 @@ impl ServerConfig {
             })
             .unwrap_or(8192);
 
+        let defaults = Self::default();
         Self {
             addr,
+            unix_socket_mode,
             max_payload_size,
             read_buffer_size,
+            header_read_timeout: env_timeout("HEADER_READ_TIMEOUT", defaults.header_read_timeout),
+            body_read_timeout: env_timeout("BODY_READ_TIMEOUT", defaults.body_read_timeout),
+            keep_alive_timeout: env_timeout("KEEP_ALIVE_TIMEOUT", defaults.keep_alive_timeout),
+            response_timeout: env_timeout("RESPONSE_TIMEOUT", defaults.response_timeout),
+            shutdown_timeout: env_timeout("SHUTDOWN_TIMEOUT", defaults.shutdown_timeout),
+            max_connections: env_limit("MAX_CONNECTIONS"),
+            max_connections_per_ip: env_limit("MAX_CONNECTIONS_PER_IP"),
+            connection_limit_policy: env::var("CONNECTION_LIMIT_POLICY")
+                .map(|s| {
+                    LimitPolicy::parse(&s)
+                        .expect("CONNECTION_LIMIT_POLICY must be one of wait, reject, close")
+                })
+                .unwrap_or_default(),
+            tls_cert: env_cert_paths(),
+            tls_sni_certs: env::var("TLS_SNI_CERTS")
+                .map(|s| parse_sni_certs(&s))
+                .unwrap_or_default(),
+            tls_alpn: env::var("TLS_ALPN")
+                .map(|s| {
+                    s.split(',')
+                        .map(|p| p.trim().to_string())
+                        .filter(|p| !p.is_empty())
+                        .collect()
+                })
+                .unwrap_or(defaults.tls_alpn),
+            tls_handshake_timeout: env_timeout(
+                "TLS_HANDSHAKE_TIMEOUT",
+                defaults.tls_handshake_timeout,
+            ),
+            tls_reload_interval: env::var("TLS_RELOAD_INTERVAL")
+                .ok()
+                .map(|_| env_timeout("TLS_RELOAD_INTERVAL", Duration::ZERO)),
+            http2_max_concurrent_streams: env::var("HTTP2_MAX_CONCURRENT_STREAMS")
+                .map(|s| {
+                    let val = s
+                        .parse()
+                        .expect("HTTP2_MAX_CONCURRENT_STREAMS must be a valid number (streams)");
+                    if val == 0 {
+                        panic!("HTTP2_MAX_CONCURRENT_STREAMS cannot be 0");
+                    }
+                    val
+                })
+                .unwrap_or(defaults.http2_max_concurrent_streams),
+            websocket_max_message_size: env::var("WEBSOCKET_MAX_MESSAGE_SIZE")
+                .map(|s| {
+                    let val = s
+                        .parse()
+                        .expect("WEBSOCKET_MAX_MESSAGE_SIZE must be a valid number (bytes)");
+                    if val == 0 {
+                        panic!("WEBSOCKET_MAX_MESSAGE_SIZE cannot be 0");
+                    }
+                    val
+                })
+                .unwrap_or(defaults.websocket_max_message_size),
+            parsing_mode: env::var("HTTP_PARSING_MODE")
+                .map(|s| {
+                    ParsingMode::parse(&s)
+                        .expect("HTTP_PARSING_MODE must be one of strict, lenient")
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether connections are served over TLS.
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() || !self.tls_sni_certs.is_empty()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: ServerAddr::Tcp(([127, 0, 0, 1], 8080).into()),
+            unix_socket_mode: None,
+            max_payload_size: 1024 * 1024,
+            read_buffer_size: 8192,
+            header_read_timeout: Duration::from_secs(10),
+            body_read_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            response_timeout: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(30),
+            max_connections: None,
+            max_connections_per_ip: None,
+            connection_limit_policy: LimitPolicy::Reject,
+            tls_cert: None,
+            tls_sni_certs: Vec::new(),
+            tls_alpn: vec!["http/1.1".to_string()],
+            tls_handshake_timeout: Duration::from_secs(10),
+            tls_reload_interval: None,
+            http2_max_concurrent_streams: 100,
+            websocket_max_message_size: 64 * 1024,
+            parsing_mode: ParsingMode::Strict,
+        }
+    }
+}
+
+/// Reads an optional connection limit from `name`.
+fn env_limit(name: &str) -> Option<usize> {
+    env::var(name).ok().map(|s| {
+        let val: usize = s
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a valid number (connections)", name));
+        if val == 0 {
+            panic!("{} cannot be 0", name);
         }
+        val
+    })
+}
+
+/// Reads `TLS_CERT_PATH` and `TLS_KEY_PATH`, which come as a pair.
+fn env_cert_paths() -> Option<CertPaths> {
+    match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        (Ok(cert), Ok(key)) => Some(CertPaths {
+            cert: cert.into(),
+            key: key.into(),
+        }),
+        (Err(_), Err(_)) => None,
+        _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    }
+}
+
+/// Parses `host=cert.pem,key.pem` entries separated by `;`.
+fn parse_sni_certs(s: &str) -> Vec<(String, CertPaths)> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(host, paths)| {
+                let (cert, key) = paths.split_once(',')?;
+                Some((
+                    host.trim().to_ascii_lowercase(),
+                    CertPaths {
+                        cert: cert.trim().into(),
+                        key: key.trim().into(),
+                    },
+                ))
+            });
+            parsed.expect("TLS_SNI_CERTS entries must look like host=cert.pem,key.pem")
+        })
+        .collect()
+}
+
+/// Reads a timeout in whole seconds from `name`.
+fn env_timeout(name: &str, default: Duration) -> Duration {
+    match env::var(name) {
+        Ok(s) => {
+            let secs: u64 = s
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a valid number (seconds)", name));
+            if secs == 0 {
+                panic!("{} cannot be 0", name);
+            }
+            Duration::from_secs(secs)
+        }
+        Err(_) => default,
     }
 }
 
@@ -105,17 +474,13 @@ mod tests_requestmeta {
                 name: "Content-Type",
                 value: b"application/json",
             },
-            Header {
-                name: "Transfer-Encoding",
-                value: b"chunked",
-            },
         ];
 
-        let meta = RequestMeta::from_headers(&headers);
+        let meta = RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap();
 
         assert_eq!(meta.content_length, Some(1024));
         assert!(matches!(meta.content_type, Some(ContentType::Json)));
-        assert!(meta.is_chunked);
+        assert!(!meta.is_chunked);
     }
 
     #[test]
@@ -131,7 +496,7 @@ mod tests_requestmeta {
             },
         ];
 
-        let meta = RequestMeta::from_headers(&headers);
+        let meta = RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap();
 
         assert_eq!(meta.content_length, Some(512));
         assert!(matches!(meta.content_type, Some(ContentType::Text)));
@@ -150,9 +515,26 @@ mod tests_requestmeta {
             },
         ];
 
-        let meta = RequestMeta::from_headers(&headers);
+        // Ошибка, но не паника, в любом режиме
+        for mode in [ParsingMode::Strict, ParsingMode::Lenient] {
+            assert_eq!(
+                RequestMeta::from_headers(&headers, mode).unwrap_err(),
+                FramingError::InvalidContentLength
+            );
+        }
+        for value in [&b"+5"[..], b"-1", b"0x10", b""] {
+            let headers = [Header {
+                name: "Content-Length",
+                value,
+            }];
+            assert!(RequestMeta::from_headers(&headers, ParsingMode::Strict).is_err());
+        }
 
-        // Должно быть None, но не паника
+        let headers = [Header {
+            name: "x-custom-header",
+            value: b"hello",
+        }];
+        let meta = RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap();
         assert_eq!(meta.content_length, None);
         assert!(meta.content_type.is_none());
         assert!(!meta.is_chunked);
@@ -171,8 +553,190 @@ mod tests_requestmeta {
             },
         ];
 
-        let meta = RequestMeta::from_headers(&headers);
-        assert_eq!(meta.content_length, Some(200));
+        // Differing lengths are rejected in any mode (RFC 9112, 6.3).
+        for mode in [ParsingMode::Strict, ParsingMode::Lenient] {
+            assert_eq!(
+                RequestMeta::from_headers(&headers, mode).unwrap_err(),
+                FramingError::ConflictingContentLength
+            );
+        }
+        let folded = [Header {
+            name: "content-length",
+            value: b"100, 200",
+        }];
+        assert_eq!(
+            RequestMeta::from_headers(&folded, ParsingMode::Strict).unwrap_err(),
+            FramingError::ConflictingContentLength
+        );
+
+        // Repeating the same length is harmless.
+        let same = [
+            Header {
+                name: "content-length",
+                value: b"100, 100",
+            },
+            Header {
+                name: "Content-Length",
+                value: b"100",
+            },
+        ];
+        let meta = RequestMeta::from_headers(&same, ParsingMode::Strict).unwrap();
+        assert_eq!(meta.content_length, Some(100));
+    }
+
+    fn transfer_encoding(value: &'static [u8]) -> RequestMeta {
+        let headers = [Header {
+            name: "Transfer-Encoding",
+            value,
+        }];
+        RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_transfer_codings() {
+        assert!(transfer_encoding(b"chunked").is_chunked);
+        assert!(transfer_encoding(b"Chunked").is_chunked);
+
+        let rejected: [(&[u8], FramingError); 5] = [
+            (b"gzip", FramingError::ChunkedNotFinal),
+            (b"chunked, gzip", FramingError::ChunkedNotFinal),
+            (b"chunked, chunked", FramingError::ChunkedNotFinal),
+            (b"notchunked", FramingError::ChunkedNotFinal),
+            (
+                b"gzip, chunked",
+                FramingError::UnsupportedTransferCoding("gzip".into()),
+            ),
+        ];
+        for (value, error) in rejected {
+            let headers = [Header {
+                name: "Transfer-Encoding",
+                value,
+            }];
+            assert_eq!(
+                RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap_err(),
+                error
+            );
+        }
+
+        // Lenient: codings before chunked are left to the application.
+        let headers = [
+            Header {
+                name: "Transfer-Encoding",
+                value: b"gzip",
+            },
+            Header {
+                name: "Transfer-Encoding",
+                value: b"chunked",
+            },
+        ];
+        let meta = RequestMeta::from_headers(&headers, ParsingMode::Lenient).unwrap();
+        assert!(meta.is_chunked);
+    }
+
+    #[test]
+    fn test_content_length_with_transfer_encoding() {
+        let headers = [
+            Header {
+                name: "Content-Length",
+                value: b"5",
+            },
+            Header {
+                name: "Transfer-Encoding",
+                value: b"chunked",
+            },
+        ];
+
+        assert_eq!(
+            RequestMeta::from_headers(&headers, ParsingMode::Strict).unwrap_err(),
+            FramingError::ContentLengthWithTransferEncoding
+        );
+
+        // Lenient: chunked wins and the connection is not reused.
+        let meta = RequestMeta::from_headers(&headers, ParsingMode::Lenient).unwrap();
+        assert!(meta.is_chunked);
+        assert_eq!(meta.content_length, None);
+        assert!(!meta.wants_keep_alive(1));
+    }
+
+    #[test]
+    fn test_keep_alive_defaults() {
+        let meta = RequestMeta::from_headers(&[], ParsingMode::Strict).unwrap();
+
+        assert!(meta.wants_keep_alive(1));
+        assert!(!meta.wants_keep_alive(0));
+    }
+
+    #[test]
+    fn test_connection_header_tokens() {
+        let close = [Header {
+            name: "Connection",
+            value: b"Upgrade, Close",
+        }];
+        let keep_alive = [Header {
+            name: "connection",
+            value: b"keep-alive",
+        }];
+
+        let meta = RequestMeta::from_headers(&close, ParsingMode::Strict).unwrap();
+        assert!(meta.connection_close);
+        assert!(!meta.wants_keep_alive(1));
+
+        let meta = RequestMeta::from_headers(&keep_alive, ParsingMode::Strict).unwrap();
+        assert!(meta.connection_keep_alive);
+        assert!(meta.wants_keep_alive(0));
+    }
+
+    #[test]
+    fn test_expect_header() {
+        let meta = RequestMeta::from_headers(
+            &[Header {
+                name: "Expect",
+                value: b"100-Continue",
+            }],
+            ParsingMode::Strict,
+        )
+        .unwrap();
+        assert!(meta.expect_continue);
+        assert!(!meta.expect_unsupported);
+
+        let meta = RequestMeta::from_headers(
+            &[Header {
+                name: "expect",
+                value: b"something-else",
+            }],
+            ParsingMode::Strict,
+        )
+        .unwrap();
+        assert!(!meta.expect_continue);
+        assert!(meta.expect_unsupported);
+
+        assert!(
+            !RequestMeta::from_headers(&[], ParsingMode::Strict)
+                .unwrap()
+                .expect_continue
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_ids {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_unique() {
+        let a = ConnectionId::next();
+        let b = ConnectionId::next();
+        assert_ne!(a, b);
+
+        let r1 = RequestId::next();
+        let r2 = RequestId::next();
+        assert!(r2 > r1);
+    }
+
+    #[test]
+    fn test_ids_display() {
+        assert_eq!(ConnectionId(7).to_string(), "conn-7");
+        assert_eq!(RequestId(42).to_string(), "req-42");
     }
 }
 
@@ -184,14 +748,53 @@ mod tests_serverconfig {
 
     fn setup_envs() {
         env::set_var("SERVER_ADDR", "127.0.0.1:8000");
+        env::set_var("UNIX_SOCKET_MODE", "660");
         env::set_var("MAX_PAYLOAD_SIZE", "1048576");
         env::set_var("READ_BUFFER_SIZE", "8192");
+        env::set_var("HEADER_READ_TIMEOUT", "3");
+        env::set_var("BODY_READ_TIMEOUT", "4");
+        env::set_var("KEEP_ALIVE_TIMEOUT", "1");
+        env::set_var("RESPONSE_TIMEOUT", "20");
+        env::set_var("SHUTDOWN_TIMEOUT", "15");
+        env::set_var("MAX_CONNECTIONS", "1000");
+        env::set_var("MAX_CONNECTIONS_PER_IP", "10");
+        env::set_var("CONNECTION_LIMIT_POLICY", "Wait");
+        env::set_var("TLS_CERT_PATH", "certs/default.pem");
+        env::set_var("TLS_KEY_PATH", "certs/default.key");
+        env::set_var(
+            "TLS_SNI_CERTS",
+            "API.example.com=certs/api.pem,certs/api.key; *.example.com=certs/w.pem,certs/w.key",
+        );
+        env::set_var("TLS_ALPN", "h2, http/1.1");
+        env::set_var("TLS_HANDSHAKE_TIMEOUT", "2");
+        env::set_var("TLS_RELOAD_INTERVAL", "60");
+        env::set_var("HTTP2_MAX_CONCURRENT_STREAMS", "250");
+        env::set_var("WEBSOCKET_MAX_MESSAGE_SIZE", "4096");
+        env::set_var("HTTP_PARSING_MODE", "Lenient");
     }
 
     fn remove_env() {
         env::remove_var("SERVER_ADDR");
+        env::remove_var("UNIX_SOCKET_MODE");
         env::remove_var("READ_BUFFER_SIZE");
         env::remove_var("MAX_PAYLOAD_SIZE");
+        env::remove_var("HEADER_READ_TIMEOUT");
+        env::remove_var("BODY_READ_TIMEOUT");
+        env::remove_var("KEEP_ALIVE_TIMEOUT");
+        env::remove_var("RESPONSE_TIMEOUT");
+        env::remove_var("SHUTDOWN_TIMEOUT");
+        env::remove_var("MAX_CONNECTIONS");
+        env::remove_var("MAX_CONNECTIONS_PER_IP");
+        env::remove_var("CONNECTION_LIMIT_POLICY");
+        env::remove_var("TLS_CERT_PATH");
+        env::remove_var("TLS_KEY_PATH");
+        env::remove_var("TLS_SNI_CERTS");
+        env::remove_var("TLS_ALPN");
+        env::remove_var("TLS_HANDSHAKE_TIMEOUT");
+        env::remove_var("TLS_RELOAD_INTERVAL");
+        env::remove_var("HTTP2_MAX_CONCURRENT_STREAMS");
+        env::remove_var("WEBSOCKET_MAX_MESSAGE_SIZE");
+        env::remove_var("HTTP_PARSING_MODE");
     }
 
     // If env is empty
@@ -203,8 +806,18 @@ mod tests_serverconfig {
         let config = ServerConfig::from_env();
 
         assert_eq!(config.addr, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.unix_socket_mode, None);
         assert_eq!(config.read_buffer_size, 8192);
         assert_eq!(config.max_payload_size, 1048576);
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(5));
+        assert_eq!(config.max_connections, None);
+        assert_eq!(config.connection_limit_policy, LimitPolicy::Reject);
+        assert!(!config.tls_enabled());
+        assert_eq!(config.tls_alpn, vec!["http/1.1"]);
+        assert_eq!(config.tls_reload_interval, None);
+        assert_eq!(config.http2_max_concurrent_streams, 100);
+        assert_eq!(config.websocket_max_message_size, 65536);
+        assert_eq!(config.parsing_mode, ParsingMode::Strict);
     }
 
     // READ_BUFFER_SIZE has incorrect value
@@ -240,6 +853,32 @@ mod tests_serverconfig {
         ServerConfig::from_env();
     }
 
+    // SERVER_ADDR names a Unix socket
+    #[test]
+    #[serial(env)]
+    fn test_config_unix_addr() {
+        setup_envs();
+        env::set_var("SERVER_ADDR", "unix:/run/aegis.sock");
+
+        let config = ServerConfig::from_env();
+
+        assert_eq!(
+            config.addr,
+            ServerAddr::Unix(std::path::PathBuf::from("/run/aegis.sock"))
+        );
+    }
+
+    // UNIX_SOCKET_MODE has incorrect value
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "UNIX_SOCKET_MODE")]
+    fn test_config_invalid_socket_mode() {
+        setup_envs();
+        env::set_var("UNIX_SOCKET_MODE", "rw-rw----");
+
+        ServerConfig::from_env();
+    }
+
     // Check custom correct values.
     #[test]
     #[serial(env)]
@@ -249,8 +888,60 @@ mod tests_serverconfig {
         let config = ServerConfig::from_env();
 
         assert_eq!(config.addr, "127.0.0.1:8000".parse().unwrap());
+        assert_eq!(config.unix_socket_mode, Some(0o660));
         assert_eq!(config.read_buffer_size, 8192);
         assert_eq!(config.max_payload_size, 1048576);
+        assert_eq!(config.header_read_timeout, Duration::from_secs(3));
+        assert_eq!(config.body_read_timeout, Duration::from_secs(4));
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(1));
+        assert_eq!(config.response_timeout, Duration::from_secs(20));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(15));
+        assert_eq!(config.max_connections, Some(1000));
+        assert_eq!(config.max_connections_per_ip, Some(10));
+        assert_eq!(config.connection_limit_policy, LimitPolicy::Wait);
+        assert!(config.tls_enabled());
+        assert_eq!(
+            config.tls_cert,
+            Some(CertPaths {
+                cert: "certs/default.pem".into(),
+                key: "certs/default.key".into(),
+            })
+        );
+        let hosts: Vec<_> = config
+            .tls_sni_certs
+            .iter()
+            .map(|(h, _)| h.as_str())
+            .collect();
+        assert_eq!(hosts, vec!["api.example.com", "*.example.com"]);
+        assert_eq!(config.tls_sni_certs[1].1.key, PathBuf::from("certs/w.key"));
+        assert_eq!(config.tls_alpn, vec!["h2", "http/1.1"]);
+        assert_eq!(config.tls_handshake_timeout, Duration::from_secs(2));
+        assert_eq!(config.tls_reload_interval, Some(Duration::from_secs(60)));
+        assert_eq!(config.http2_max_concurrent_streams, 250);
+        assert_eq!(config.websocket_max_message_size, 4096);
+        assert_eq!(config.parsing_mode, ParsingMode::Lenient);
+    }
+
+    // WEBSOCKET_MAX_MESSAGE_SIZE has incorrect value
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "WEBSOCKET_MAX_MESSAGE_SIZE")]
+    fn test_config_invalid_websocket_message_size() {
+        setup_envs();
+        env::set_var("WEBSOCKET_MAX_MESSAGE_SIZE", "64k");
+
+        ServerConfig::from_env();
+    }
+
+    // Test edge case (0) in HTTP2_MAX_CONCURRENT_STREAMS
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "HTTP2_MAX_CONCURRENT_STREAMS")]
+    fn test_edge_zero_http2_streams() {
+        setup_envs();
+        env::set_var("HTTP2_MAX_CONCURRENT_STREAMS", "0");
+
+        ServerConfig::from_env();
     }
 
     // Test edge case (0) in READ_BUFFER_SIZE
@@ -274,4 +965,75 @@ mod tests_serverconfig {
 
         ServerConfig::from_env();
     }
+
+    // Timeouts are whole seconds and cannot be 0.
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "KEEP_ALIVE_TIMEOUT cannot be 0")]
+    fn test_edge_zero_case_timeout() {
+        setup_envs();
+        env::set_var("KEEP_ALIVE_TIMEOUT", "0");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "RESPONSE_TIMEOUT")]
+    fn test_config_invalid_timeout() {
+        setup_envs();
+        env::set_var("RESPONSE_TIMEOUT", "1.5s");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "MAX_CONNECTIONS_PER_IP cannot be 0")]
+    fn test_edge_zero_case_connection_limit() {
+        setup_envs();
+        env::set_var("MAX_CONNECTIONS_PER_IP", "0");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "CONNECTION_LIMIT_POLICY")]
+    fn test_config_invalid_limit_policy() {
+        setup_envs();
+        env::set_var("CONNECTION_LIMIT_POLICY", "queue");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "HTTP_PARSING_MODE")]
+    fn test_config_invalid_parsing_mode() {
+        setup_envs();
+        env::set_var("HTTP_PARSING_MODE", "loose");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "TLS_CERT_PATH and TLS_KEY_PATH")]
+    fn test_config_cert_without_key() {
+        setup_envs();
+        env::remove_var("TLS_KEY_PATH");
+
+        ServerConfig::from_env();
+    }
+
+    #[test]
+    #[serial(env)]
+    #[should_panic(expected = "TLS_SNI_CERTS")]
+    fn test_config_invalid_sni_certs() {
+        setup_envs();
+        env::set_var("TLS_SNI_CERTS", "api.example.com=certs/api.pem");
+
+        ServerConfig::from_env();
+    }
 }
@@ -0,0 +1,243 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UriError {
+    Empty,
+    InvalidAuthority,
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty request target"),
+            Self::InvalidAuthority => write!(f, "invalid authority in request target"),
+        }
+    }
+}
+
+impl std::error::Error for UriError {}
+
+/// Request target of an HTTP/1.x request split into its components.
+///
+/// `path` and `query` are kept exactly as received; `segments` and
+/// `query_params` are percent-decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Uri {
+    pub raw: String,
+    // Host and port of absolute-form and authority-form (CONNECT) targets.
+    pub authority: Option<String>,
+    pub path: String,
+    pub query: Option<String>,
+    pub segments: Vec<String>,
+    pub query_params: Vec<(String, String)>,
+}
+
+impl Uri {
+    /// Parses an origin-form (`/a/b?x=1`), absolute-form
+    /// (`http://host/a`), authority-form (`host:443`) or asterisk-form
+    /// (`*`) request target.
+    pub fn parse(target: &str) -> Result<Self, UriError> {
+        if target.is_empty() {
+            return Err(UriError::Empty);
+        }
+
+        let mut authority = None;
+        let mut authority_form = false;
+        let mut rest = target;
+        // CONDITION: the scheme has to end before any `/` or `?`, so a URL
+        // in the query of an origin-form target is not taken for one.
+        let scheme_end = target
+            .find("://")
+            .filter(|&end| !target[..end].contains(['/', '?']));
+        if let Some(scheme_end) = scheme_end {
+            let after_scheme = &target[scheme_end + 3..];
+            let authority_end = after_scheme.find(['/', '?']).unwrap_or(after_scheme.len());
+            if authority_end == 0 {
+                return Err(UriError::InvalidAuthority);
+            }
+            authority = Some(after_scheme[..authority_end].to_string());
+            rest = &after_scheme[authority_end..];
+        } else if !target.starts_with('/') && target != "*" {
+            // authority-form carries no path at all.
+            authority = Some(target.to_string());
+            authority_form = true;
+            rest = "";
+        }
+
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (rest, None),
+        };
+        let path = match path {
+            "" if authority_form => "",
+            "" => "/",
+            path => path,
+        };
+
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode(segment, false))
+            .collect();
+        let query_params = query.as_deref().map(parse_query).unwrap_or_default();
+
+        Ok(Self {
+            raw: target.to_string(),
+            authority,
+            path: path.to_string(),
+            query,
+            segments,
+            query_params,
+        })
+    }
+
+    /// Percent-decoded path. `%2F` decodes to `/`, so use `segments` when
+    /// the segment boundaries matter.
+    pub fn decoded_path(&self) -> String {
+        percent_decode(&self.path, false)
+    }
+
+    /// First decoded value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// Splits `a=1&b=two+words` into decoded pairs. Keys without `=` get an
+/// empty value.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set
+/// (form encoding). Malformed escapes are kept literally and invalid
+/// UTF-8 is replaced.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        out.push(high << 4 | low);
+                        i += 3;
+                        continue;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => out.push(b' '),
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_form() {
+        let uri = Uri::parse("/users/42/files?sort=desc&limit=10").unwrap();
+
+        assert_eq!(uri.path, "/users/42/files");
+        assert_eq!(uri.query.as_deref(), Some("sort=desc&limit=10"));
+        assert_eq!(uri.segments, vec!["users", "42", "files"]);
+        assert_eq!(uri.query_param("limit"), Some("10"));
+        assert_eq!(uri.query_param("missing"), None);
+        assert!(uri.authority.is_none());
+    }
+
+    #[test]
+    fn test_percent_decoding() {
+        let uri = Uri::parse("/files/a%20b/c%2Fd?q=caf%C3%A9+au+lait&x=%ZZ").unwrap();
+
+        assert_eq!(uri.segments, vec!["files", "a b", "c/d"]);
+        assert_eq!(uri.decoded_path(), "/files/a b/c/d");
+        assert_eq!(uri.query_param("q"), Some("café au lait"));
+        assert_eq!(uri.query_param("x"), Some("%ZZ"));
+    }
+
+    #[test]
+    fn test_query_edge_cases() {
+        let uri = Uri::parse("/?flag&a=1&a=2&&=empty&trail=%").unwrap();
+
+        assert_eq!(
+            uri.query_params,
+            vec![
+                ("flag".to_string(), "".to_string()),
+                ("a".to_string(), "1".to_string()),
+                ("a".to_string(), "2".to_string()),
+                ("".to_string(), "empty".to_string()),
+                ("trail".to_string(), "%".to_string()),
+            ]
+        );
+        assert_eq!(uri.query_param("a"), Some("1"));
+    }
+
+    #[test]
+    fn test_absolute_and_authority_form() {
+        let uri = Uri::parse("http://example.com:8080/a?b=c").unwrap();
+        assert_eq!(uri.authority.as_deref(), Some("example.com:8080"));
+        assert_eq!(uri.path, "/a");
+        assert_eq!(uri.query_param("b"), Some("c"));
+
+        let uri = Uri::parse("http://example.com").unwrap();
+        assert_eq!(uri.path, "/");
+
+        let uri = Uri::parse("example.com:443").unwrap();
+        assert_eq!(uri.authority.as_deref(), Some("example.com:443"));
+        assert_eq!(uri.path, "");
+
+        assert_eq!(Uri::parse("http:///a"), Err(UriError::InvalidAuthority));
+    }
+
+    #[test]
+    fn test_url_in_query_is_not_an_authority() {
+        let uri = Uri::parse("/login?next=http://evil.com/home").unwrap();
+        assert!(uri.authority.is_none());
+        assert_eq!(uri.path, "/login");
+        assert_eq!(uri.query_param("next"), Some("http://evil.com/home"));
+
+        let uri = Uri::parse("/redirect/http://evil.com/home").unwrap();
+        assert!(uri.authority.is_none());
+        assert_eq!(uri.segments, vec!["redirect", "http:", "evil.com", "home"]);
+    }
+
+    #[test]
+    fn test_asterisk_and_root() {
+        assert_eq!(Uri::parse("*").unwrap().path, "*");
+
+        let root = Uri::parse("/").unwrap();
+        assert_eq!(root.path, "/");
+        assert!(root.segments.is_empty());
+
+        assert_eq!(Uri::parse(""), Err(UriError::Empty));
+    }
+}
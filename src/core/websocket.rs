@@ -0,0 +1,209 @@
+use std::fmt;
+use tokio::sync::mpsc;
+
+use crate::core::headers::HeaderMap;
+use crate::core::response::Response;
+
+// Messages queued for one WebSocket before the sender has to wait.
+const SEND_CHANNEL_CAPACITY: usize = 16;
+
+/// Close codes from RFC 6455, section 7.4.1.
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    // Never sent: reported when a close frame carried no code.
+    pub const NO_STATUS: u16 = 1005;
+    // Never sent: reported when the connection dropped without a close frame.
+    pub const ABNORMAL: u16 = 1006;
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+/// A complete WebSocket message. Fragmented messages are reassembled
+/// before they are reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    // Pings are answered by the server; they are reported all the same.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+/// What the application sends for a WebSocket: first the answer to the
+/// handshake, then messages until it closes.
+#[derive(Debug)]
+pub enum WebSocketSend {
+    /// Completes the handshake with `101 Switching Protocols`, choosing one
+    /// of the subprotocols the client offered, if any.
+    Accept {
+        protocol: Option<String>,
+        // Extra headers for the 101 response, e.g. cookies. Headers the
+        // handshake sets itself are left as they are.
+        headers: HeaderMap,
+    },
+    /// Answers the handshake with a plain HTTP response instead.
+    Reject(Response),
+    Message(Message),
+    /// Starts the closing handshake.
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WebSocketError {
+    /// The connection is gone.
+    Closed,
+    /// Messages were sent before `accept`.
+    NotAccepted,
+    AlreadyAccepted,
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "WebSocket connection closed"),
+            Self::NotAccepted => write!(f, "WebSocket handshake not accepted yet"),
+            Self::AlreadyAccepted => write!(f, "WebSocket handshake already answered"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+/// Sending half of a WebSocket, handed to the application with
+/// `Event::WebSocketConnect`.
+///
+/// The handshake is answered with [`accept`](Self::accept) or
+/// [`reject`](Self::reject). Dropping the sender closes the WebSocket
+/// normally, or rejects the handshake with `403 Forbidden` if it was
+/// never answered.
+#[derive(Debug)]
+pub struct WebSocketSender {
+    tx: mpsc::Sender<WebSocketSend>,
+    accepted: bool,
+    headers: HeaderMap,
+}
+
+impl WebSocketSender {
+    pub fn channel() -> (Self, mpsc::Receiver<WebSocketSend>) {
+        let (tx, rx) = mpsc::channel(SEND_CHANNEL_CAPACITY);
+        let sender = Self {
+            tx,
+            accepted: false,
+            headers: HeaderMap::new(),
+        };
+        (sender, rx)
+    }
+
+    pub async fn accept(&mut self, protocol: Option<&str>) -> Result<(), WebSocketError> {
+        if self.accepted {
+            return Err(WebSocketError::AlreadyAccepted);
+        }
+        self.accepted = true;
+        let protocol = protocol.map(str::to_string);
+        let headers = std::mem::take(&mut self.headers);
+        self.send_raw(WebSocketSend::Accept { protocol, headers })
+            .await
+    }
+
+    pub async fn reject(self, response: Response) -> Result<(), WebSocketError> {
+        if self.accepted {
+            return Err(WebSocketError::AlreadyAccepted);
+        }
+        self.send_raw(WebSocketSend::Reject(response)).await
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if !self.accepted {
+            return Err(WebSocketError::NotAccepted);
+        }
+        self.send_raw(WebSocketSend::Message(message)).await
+    }
+
+    pub async fn text(&mut self, text: impl Into<String>) -> Result<(), WebSocketError> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    pub async fn binary(&mut self, data: Vec<u8>) -> Result<(), WebSocketError> {
+        self.send(Message::Binary(data)).await
+    }
+
+    /// Starts the closing handshake. The connection ends once the client
+    /// answers, which is reported as `Event::WebSocketDisconnect`.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !self.accepted {
+            return Err(WebSocketError::NotAccepted);
+        }
+        let reason = reason.to_string();
+        self.send_raw(WebSocketSend::Close { code, reason }).await
+    }
+
+    /// Whether the connection that would receive the messages is gone.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Headers `accept` adds to the 101 response.
+    #[cfg(feature = "service")]
+    pub(crate) fn handshake_headers(&mut self, headers: HeaderMap) {
+        self.headers = headers;
+    }
+
+    /// A handle that can close the WebSocket without keeping it open, so
+    /// dropping the sender still ends it.
+    #[cfg(feature = "service")]
+    pub(crate) fn downgrade(&self) -> mpsc::WeakSender<WebSocketSend> {
+        self.tx.downgrade()
+    }
+
+    async fn send_raw(&self, message: WebSocketSend) -> Result<(), WebSocketError> {
+        self.tx
+            .send(message)
+            .await
+            .map_err(|_| WebSocketError::Closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_messages_need_accept() {
+        let (mut sender, mut rx) = WebSocketSender::channel();
+
+        assert_eq!(sender.text("early").await, Err(WebSocketError::NotAccepted));
+        sender.accept(Some("chat")).await.unwrap();
+        assert_eq!(
+            sender.accept(None).await,
+            Err(WebSocketError::AlreadyAccepted)
+        );
+        sender.text("hi").await.unwrap();
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(WebSocketSend::Accept { protocol: Some(p), .. }) if p == "chat"
+        ));
+        assert!(matches!(
+            rx.recv().await,
+            Some(WebSocketSend::Message(Message::Text(t))) if t == "hi"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send_after_connection_is_gone() {
+        let (mut sender, rx) = WebSocketSender::channel();
+        sender.accept(None).await.unwrap();
+        drop(rx);
+
+        assert!(sender.is_closed());
+        assert_eq!(sender.binary(vec![1]).await, Err(WebSocketError::Closed));
+    }
+}
@@ -0,0 +1,36 @@
+//! Aegis: an asynchronous HTTP/1.1 server.
+//!
+//! The transport layer (`Listener`, `ByteStream`) feeds `run_server`, which
+//! parses requests and reports them as `Event`s on a channel. Applications
+//! either consume those events directly or, with the `service` feature,
+//! implement `Handler` and let `Server` do the dispatching.
+
+pub mod core;
+pub mod protocols;
+#[cfg(feature = "service")]
+pub mod service;
+
+pub use crate::core::addr::{PeerAddr, ServerAddr};
+pub use crate::core::events::{Event, LifespanAck};
+pub use crate::core::handle::{ServerHandle, ServerStats};
+pub use crate::core::response::{Response, ResponseSender};
+pub use crate::core::sse::{SseEvent, SseSender, SseStream};
+pub use crate::core::structs::ServerConfig;
+pub use crate::core::websocket::{Message, WebSocketSender};
+pub use crate::protocols::tcp::connection::ByteStream;
+pub use crate::protocols::tcp::listener::Listener;
+#[cfg(feature = "testing")]
+pub use crate::protocols::tcp::memory::{MemoryConnector, MemoryListener, TestClient};
+pub use crate::protocols::tcp::server::{run_server, run_server_with_handle};
+#[cfg(feature = "tls")]
+pub use crate::protocols::tls::listener::TlsListener;
+
+#[cfg(feature = "service")]
+pub use crate::service::{
+    handler::Handler,
+    middleware::{Middleware, Next},
+    request::Request,
+    router::Router,
+    server::Server,
+    websocket::WebSocket,
+};
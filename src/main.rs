@@ -1,17 +1,8 @@
 use dotenvy::dotenv;
-use std::sync::Arc;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, prelude::*, registry, EnvFilter};
 
-pub mod core;
-pub mod protocols;
-
-use crate::core::events::Event;
-use crate::core::response::Response;
-use crate::core::structs::ServerConfig;
-use crate::protocols::tcp::listener::TcpByteListener;
-use crate::protocols::tcp::server;
+use aegis::{Request, Response, Server, ServerConfig, ServerHandle};
 
 fn init_logging() {
     dotenv().ok();
@@ -21,58 +12,60 @@ fn init_logging() {
         .init();
 }
 
+async fn status(request: Request) -> Response {
+    info!(
+        request = %request.request_id,
+        "New Request: {} {} {:?} (Content-Length: {:?})",
+        request.method, request.uri.path, request.meta.content_type, request.meta.content_length
+    );
+
+    Response::ok()
+        .header("Content-Type", "application/json")
+        .body(b"{\"status\": \"Aegis is running\"}".to_vec())
+}
+
+/// Triggers a graceful shutdown on SIGINT or SIGTERM.
+async fn shutdown_on_signal(handle: ServerHandle) {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+    handle.shutdown();
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 1. Configurate
     init_logging();
-    let config = Arc::new(ServerConfig::from_env());
+    let config = ServerConfig::from_env();
     let span = tracing::info_span!("connection", server = %config.addr);
     let _enter = span.enter();
 
-    // 2. Event channel.
-    let (tx, mut rx) = mpsc::channel(100);
+    // 2. Wire the handler to the transport
+    let server = Server::builder().config(config).handler(status).build()?;
 
-    // 3. Transport initialization
-    let tcp_listener = TcpByteListener::bind(config.addr).await?;
-    let listener = Box::new(tcp_listener);
-    let server_config = Arc::clone(&config);
-
-    info!("Server starting");
+    // 3. Stop gracefully on SIGINT/SIGTERM
+    tokio::spawn(shutdown_on_signal(server.handle()));
 
     // 4. Start the server
-    tokio::spawn(async move {
-        if let Err(e) = server::run_server(listener, tx, server_config).await {
-            error!("Server Error: {}", e);
-        }
-    });
-
-    // 5. App logic
-    info!("Event loop started");
-    while let Some(event) = rx.recv().await {
-        match event {
-            Event::RequestStart { meta, resp_tx, .. } => {
-                info!(
-                    "New Request: {:?} (Content-Length: {:?})",
-                    meta.content_type, meta.content_length
-                );
-                let response = Response::ok()
-                    .header("Content-Type", "application/json")
-                    .body(b"{\"status\": \"Aegis is running\"}".to_vec());
-
-                if resp_tx.send(response).is_err() {
-                    error!("Receiver already dropped - request cancelled");
-                }
-            }
-            Event::RequestBody { more_body, .. } => {
-                if !more_body {
-                    info!("Body fully received");
-                }
-            }
-            Event::Disconnect { client_addr: _ } => {
-                info!("Client disconnected");
-            }
-        }
-    }
+    info!("Server starting");
+    server.run().await?;
 
     Ok(())
 }
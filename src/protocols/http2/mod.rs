@@ -0,0 +1,507 @@
+//! HTTP/2 connections, reached through TLS ALPN (`h2`), a cleartext
+//! connection that starts with the preface (prior knowledge), or an
+//! HTTP/1.1 `Upgrade: h2c` request.
+//!
+//! Every stream is reported like an HTTP/1.x request with `version == 2`,
+//! so applications serve both the same way.
+
+mod rewind;
+mod stream;
+mod upgrade;
+
+pub(crate) use rewind::Rewind;
+pub(crate) use upgrade::{is_preface_start, upgrade_frame, PREFACE};
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::{sleep_until, timeout, Instant};
+use tracing::{debug, Instrument};
+
+use crate::core::addr::PeerAddr;
+use crate::core::events::Event;
+use crate::core::handle::ServerHandle;
+use crate::core::structs::{ConnectionId, RequestId, ServerConfig};
+
+/// What every stream of a connection needs to report its request.
+#[derive(Clone)]
+pub(crate) struct Conn {
+    pub(crate) connection_id: ConnectionId,
+    pub(crate) client_addr: PeerAddr,
+    pub(crate) tx: mpsc::Sender<Event>,
+    pub(crate) config: Arc<ServerConfig>,
+}
+
+/// Serves an HTTP/2 connection until the client closes it, it idles for
+/// `keep_alive_timeout`, or the server shuts down.
+///
+/// `upgrade` is the HEADERS frame from `upgrade_frame` when the connection
+/// switched from HTTP/1.1; the client then still has to send the preface.
+pub(crate) async fn serve_connection(
+    mut io: Rewind,
+    upgrade: Option<Vec<u8>>,
+    conn: Conn,
+    handle: ServerHandle,
+) {
+    let config = Arc::clone(&conn.config);
+
+    // 1. Handshake, within the time allowed for a request head.
+    let handshake = async {
+        if let Some(frame) = &upgrade {
+            upgrade::splice(&mut io, frame).await?;
+        }
+        h2::server::Builder::new()
+            .max_concurrent_streams(config.http2_max_concurrent_streams)
+            .max_header_list_size(config.max_payload_size.try_into().unwrap_or(u32::MAX))
+            .handshake::<_, Bytes>(io)
+            .await
+            .map_err(std::io::Error::other)
+    };
+    let mut connection = match timeout(config.header_read_timeout, handshake).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+            debug!("HTTP/2 handshake failed: {}", e);
+            disconnect(&conn, None).await;
+            return;
+        }
+        Err(_) => {
+            debug!("HTTP/2 handshake timed out");
+            disconnect(&conn, None).await;
+            return;
+        }
+    };
+    debug!("HTTP/2 connection established");
+
+    // Request of every stream task, to report the ones cut short.
+    let mut streams = JoinSet::new();
+    let mut in_flight: HashMap<tokio::task::Id, RequestId> = HashMap::new();
+    let mut idle_since = Instant::now();
+    let mut draining = false;
+
+    loop {
+        let idle_deadline =
+            (streams.is_empty() && !draining).then(|| idle_since + config.keep_alive_timeout);
+
+        tokio::select! {
+            // 2. Accepting streams. Polling `accept` also drives the
+            //    connection, so it runs for as long as the connection lives.
+            accepted = connection.accept() => match accepted {
+                Some(Ok((request, respond))) => {
+                    let id = RequestId::next();
+                    let span = tracing::debug_span!("h2_stream", request = %id);
+                    let task = streams.spawn(
+                        stream::serve_stream(request, respond, id, conn.clone()).instrument(span),
+                    );
+                    in_flight.insert(task.id(), id);
+                }
+                Some(Err(e)) => {
+                    debug!("HTTP/2 connection failed: {}", e);
+                    break;
+                }
+                None => {
+                    debug!("HTTP/2 connection closed");
+                    break;
+                }
+            },
+            Some(finished) = streams.join_next_with_id(), if !streams.is_empty() => {
+                let task = match finished {
+                    Ok((task, ())) => task,
+                    Err(e) => e.id(),
+                };
+                in_flight.remove(&task);
+                if streams.is_empty() {
+                    idle_since = Instant::now();
+                }
+            }
+            // 3. Letting open streams finish, then closing, on shutdown.
+            _ = handle.wait(), if !draining => {
+                debug!("Closing HTTP/2 connection for shutdown");
+                draining = true;
+                connection.graceful_shutdown();
+            }
+            // 4. Closing an idle connection.
+            _ = async {
+                match idle_deadline {
+                    Some(at) => sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                debug!("Closing idle HTTP/2 connection");
+                draining = true;
+                connection.graceful_shutdown();
+            }
+        }
+    }
+
+    // Streams still open lost their connection.
+    drop(connection);
+    streams.abort_all();
+    while let Some(finished) = streams.join_next_with_id().await {
+        if let Ok((task, ())) = finished {
+            in_flight.remove(&task);
+        }
+    }
+    for request_id in in_flight.into_values() {
+        disconnect(&conn, Some(request_id)).await;
+    }
+    disconnect(&conn, None).await;
+}
+
+async fn disconnect(conn: &Conn, request_id: Option<RequestId>) {
+    let _ = conn
+        .tx
+        .send(Event::Disconnect {
+            connection_id: conn.connection_id,
+            request_id,
+            client_addr: conn.client_addr.clone(),
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::HttpStatus;
+    use crate::core::response::{Response, ResponseSender};
+    use crate::protocols::tcp::memory::{MemoryConnector, MemoryListener, TestClient};
+    use crate::protocols::tcp::server::run_server;
+    use h2::client::SendRequest;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// Answers every request with its body, and the version, target and
+    /// host it was seen with in `X-Summary`.
+    async fn echo_app(mut rx: mpsc::Receiver<Event>) {
+        let mut pending: HashMap<RequestId, (ResponseSender, String, Vec<u8>)> = HashMap::new();
+        while let Some(event) = rx.recv().await {
+            match event {
+                Event::RequestStart {
+                    request_id,
+                    uri,
+                    version,
+                    headers,
+                    rest,
+                    more_body,
+                    resp_tx,
+                    ..
+                } => {
+                    let summary = format!(
+                        "{} {} {}",
+                        version,
+                        uri,
+                        headers.get_str("host").unwrap_or("")
+                    );
+                    if more_body {
+                        pending.insert(request_id, (resp_tx, summary, rest));
+                    } else {
+                        let _ =
+                            resp_tx.send(Response::ok().header("X-Summary", &summary).body(rest));
+                    }
+                }
+                Event::RequestBody {
+                    request_id,
+                    body,
+                    more_body,
+                    ..
+                } => {
+                    if let Some((_, _, received)) = pending.get_mut(&request_id) {
+                        received.extend_from_slice(&body);
+                    }
+                    if !more_body {
+                        if let Some((resp_tx, summary, received)) = pending.remove(&request_id) {
+                            let response = Response::ok().header("X-Summary", &summary);
+                            let _ = resp_tx.send(response.body(received));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Starts a server and acknowledges its startup.
+    async fn spawn_server(config: ServerConfig) -> (MemoryConnector, mpsc::Receiver<Event>) {
+        let (listener, connector) = MemoryListener::new();
+        let (tx, mut rx) = mpsc::channel(64);
+        tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config)));
+        if let Some(Event::Startup { ack }) = rx.recv().await {
+            ack.ok();
+        }
+        (connector, rx)
+    }
+
+    async fn spawn_echo(config: ServerConfig) -> MemoryConnector {
+        let (connector, rx) = spawn_server(config).await;
+        tokio::spawn(echo_app(rx));
+        connector
+    }
+
+    async fn client(connector: &MemoryConnector) -> SendRequest<Bytes> {
+        let io = connector.connect().await.unwrap();
+        let (client, connection) = h2::client::handshake(io).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    /// Sends a request and reads the whole response.
+    async fn fetch(
+        client: &SendRequest<Bytes>,
+        method: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<(http::response::Parts, Vec<u8>), h2::Error> {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(format!("http://aegis.test{}", path))
+            .body(())
+            .unwrap();
+        let mut client = client.clone().ready().await?;
+        let (response, mut stream) = client.send_request(request, body.is_none())?;
+        if let Some(body) = body {
+            stream.send_data(Bytes::from(body), true)?;
+        }
+
+        let (parts, mut recv) = response.await?.into_parts();
+        let mut body = Vec::new();
+        while let Some(chunk) = recv.data().await {
+            let chunk = chunk?;
+            let _ = recv.flow_control().release_capacity(chunk.len());
+            body.extend_from_slice(&chunk);
+        }
+        Ok((parts, body))
+    }
+
+    fn summary(parts: &http::response::Parts) -> &str {
+        parts.headers["x-summary"].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prior_knowledge() {
+        let connector = spawn_echo(ServerConfig::default()).await;
+        let client = client(&connector).await;
+
+        let (parts, body) = fetch(&client, "GET", "/a?b=1", None).await.unwrap();
+        assert_eq!(parts.status, 200);
+        assert_eq!(summary(&parts), "2 /a?b=1 aegis.test");
+        assert!(body.is_empty());
+        // HTTP/1.x connection headers never reach an HTTP/2 client.
+        assert!(!parts.headers.contains_key("connection"));
+
+        let (parts, body) = fetch(&client, "POST", "/echo", Some(b"hello".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(summary(&parts), "2 /echo aegis.test");
+        assert_eq!(body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_streams_are_multiplexed() {
+        let (connector, mut rx) = spawn_server(ServerConfig::default()).await;
+        let client = client(&connector).await;
+
+        let first = tokio::spawn({
+            let client = client.clone();
+            async move { fetch(&client, "GET", "/first", None).await }
+        });
+        let second = tokio::spawn({
+            let client = client.clone();
+            async move { fetch(&client, "GET", "/second", None).await }
+        });
+
+        // Both requests are in flight at once; answer them in reverse.
+        let mut started = Vec::new();
+        while started.len() < 2 {
+            if let Some(Event::RequestStart { uri, resp_tx, .. }) = rx.recv().await {
+                started.push((uri.path, resp_tx));
+            }
+        }
+        for (path, resp_tx) in started.into_iter().rev() {
+            let _ = resp_tx.send(Response::ok().body(path.into_bytes()));
+        }
+
+        assert_eq!(first.await.unwrap().unwrap().1, b"/first");
+        assert_eq!(second.await.unwrap().unwrap().1, b"/second");
+    }
+
+    #[tokio::test]
+    async fn test_flow_control_both_ways() {
+        // Well past the 64 KiB initial windows.
+        let connector = spawn_echo(ServerConfig::default()).await;
+        let client = client(&connector).await;
+
+        let payload: Vec<u8> = (0..300_000).map(|i| i as u8).collect();
+        let (parts, body) = fetch(&client, "PUT", "/big", Some(payload.clone()))
+            .await
+            .unwrap();
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, payload);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let (connector, mut rx) = spawn_server(ServerConfig {
+            max_payload_size: 1024,
+            ..ServerConfig::default()
+        })
+        .await;
+        let client = client(&connector).await;
+
+        // Announced up front: rejected before the application sees it.
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("http://aegis.test/")
+            .header("content-length", "2048")
+            .body(())
+            .unwrap();
+        let (response, _stream) = client.clone().send_request(request, false).unwrap();
+        assert_eq!(response.await.unwrap().status(), 413);
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::RequestRejected {
+                request_id: None,
+                status: HttpStatus::ContentTooLarge,
+                ..
+            })
+        ));
+
+        // Streamed: rejected once the limit is crossed.
+        let fetched = tokio::spawn({
+            let client = client.clone();
+            async move { fetch(&client, "POST", "/", Some(vec![0; 2048])).await }
+        });
+        let mut rejected = None;
+        // Held so the request is not answered with a 500 instead.
+        let mut held = Vec::new();
+        while rejected.is_none() {
+            match rx.recv().await.unwrap() {
+                Event::RequestRejected {
+                    request_id, status, ..
+                } => rejected = Some((request_id, status)),
+                Event::RequestStart { resp_tx, .. } => held.push(resp_tx),
+                _ => continue,
+            }
+        }
+        let (request_id, status) = rejected.unwrap();
+        assert!(request_id.is_some());
+        assert_eq!(status, HttpStatus::ContentTooLarge);
+        assert_eq!(fetched.await.unwrap().unwrap().0.status, 413);
+
+        // The connection itself is still usable.
+        let request = http::Request::get("http://aegis.test/").body(()).unwrap();
+        let (response, _) = client.clone().send_request(request, true).unwrap();
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        let _ = resp_tx.send(Response::ok());
+        assert_eq!(response.await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue() {
+        let (connector, mut rx) = spawn_server(ServerConfig::default()).await;
+        let client = client(&connector).await;
+        let upload = || {
+            http::Request::put("http://aegis.test/")
+                .header("expect", "100-continue")
+                .body(())
+                .unwrap()
+        };
+
+        // 1. Asked for: the body follows the go-ahead.
+        let (response, mut stream) = client.clone().send_request(upload(), false).unwrap();
+        let Some(Event::RequestStart { resp_tx, meta, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        assert!(meta.expect_continue);
+        resp_tx.send_continue().await.unwrap();
+        stream.send_data(Bytes::from_static(b"abc"), true).unwrap();
+        let Some(Event::RequestBody { body, .. }) = rx.recv().await else {
+            panic!("expected RequestBody");
+        };
+        assert_eq!(body, b"abc");
+        let _ = resp_tx.send(Response::ok());
+        assert_eq!(response.await.unwrap().status(), 200);
+
+        // 2. Refused: the stream ends without the body.
+        let (response, _stream) = client.clone().send_request(upload(), false).unwrap();
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        let _ = resp_tx.send(Response::error(HttpStatus::ExpectationFailed));
+        assert_eq!(response.await.unwrap().status(), 417);
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::Disconnect {
+                request_id: Some(_),
+                ..
+            })
+        ));
+    }
+
+    /// Reads one frame: type, flags, stream id and payload.
+    async fn read_frame(io: &mut DuplexStream) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0u8; 9];
+        io.read_exact(&mut header).await.unwrap();
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        let mut payload = vec![0; length];
+        io.read_exact(&mut payload).await.unwrap();
+        (header[3], header[4], stream_id & 0x7fff_ffff, payload)
+    }
+
+    #[tokio::test]
+    async fn test_h2c_upgrade() {
+        let connector = spawn_echo(ServerConfig::default()).await;
+        let mut io = connector.connect().await.unwrap();
+
+        io.write_all(
+            b"GET /up HTTP/1.1\r\nHost: aegis.test\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        let switching =
+            b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+        let mut head = vec![0; switching.len()];
+        io.read_exact(&mut head).await.unwrap();
+        assert_eq!(head, switching);
+
+        // Preface and an empty SETTINGS frame.
+        io.write_all(PREFACE).await.unwrap();
+        io.write_all(&[0, 0, 0, 0x4, 0, 0, 0, 0, 0]).await.unwrap();
+
+        // The upgraded request is answered on stream 1.
+        let mut saw_headers = false;
+        loop {
+            let (kind, flags, stream_id, _) = read_frame(&mut io).await;
+            if stream_id != 1 {
+                continue;
+            }
+            match kind {
+                0x1 => saw_headers = true,
+                // DATA with END_STREAM: the empty echoed body.
+                0x0 if flags & 0x1 != 0 => break,
+                _ => {}
+            }
+        }
+        assert!(saw_headers);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_with_body_stays_http1() {
+        let connector = spawn_echo(ServerConfig::default()).await;
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+
+        let response = client
+            .request(
+                b"POST /up HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\
+                  HTTP2-Settings: AAMAAABkAAQAAP__\r\nContent-Length: 2\r\n\r\nhi",
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.headers.get_str("X-Summary"), Some("1 /up"));
+        assert_eq!(response.body, b"hi");
+    }
+}
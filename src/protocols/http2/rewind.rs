@@ -0,0 +1,84 @@
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::protocols::tcp::connection::ByteStream;
+
+/// A stream that first replays bytes already read from `inner`, e.g. the
+/// connection preface the HTTP/1.1 parser looked at.
+pub(crate) struct Rewind {
+    prefix: Vec<u8>,
+    // Bytes of `prefix` already handed out.
+    pos: usize,
+    inner: Box<dyn ByteStream>,
+}
+
+impl Rewind {
+    pub(crate) fn new(prefix: Vec<u8>, inner: Box<dyn ByteStream>) -> Self {
+        Self {
+            prefix,
+            pos: 0,
+            inner,
+        }
+    }
+
+    /// Bytes that will be read before anything new from the stream.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.prefix[self.pos..]
+    }
+
+    /// Reads more bytes from the stream into the replayed prefix. Returns 0
+    /// at end of stream.
+    pub(crate) async fn fill(&mut self) -> Result<usize> {
+        let mut buf = [0u8; 4096];
+        let n = self.inner.read(&mut buf).await?;
+        self.prefix.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Inserts `bytes` at `at` bytes into what is still buffered.
+    pub(crate) fn insert(&mut self, at: usize, bytes: &[u8]) {
+        let at = self.pos + at;
+        self.prefix.splice(at..at, bytes.iter().copied());
+    }
+}
+
+impl AsyncRead for Rewind {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = &mut *self;
+        if this.pos < this.prefix.len() {
+            let n = buf.remaining().min(this.prefix.len() - this.pos);
+            buf.put_slice(&this.prefix[this.pos..this.pos + n]);
+            this.pos += n;
+            if this.pos == this.prefix.len() {
+                this.prefix = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Rewind {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,423 @@
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{Reason, RecvStream, SendStream};
+use std::future::poll_fn;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, warn};
+
+use crate::core::enums::{HttpStatus, Method, ParsingMode, Timeout};
+use crate::core::events::Event;
+use crate::core::headers::HeaderMap;
+use crate::core::response::{Response, ResponseMessage, ResponseSender};
+use crate::core::structs::{RequestId, RequestMeta};
+use crate::core::uri::Uri;
+
+use super::Conn;
+
+// Response headers that are specific to an HTTP/1.x connection.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Progress of the response on a stream.
+enum ResponseState {
+    // Waiting for `ResponseMessage::Start`.
+    Pending,
+    // `None` when the response has no body, e.g. to a HEAD request.
+    Streaming(Option<SendStream<Bytes>>),
+    Complete,
+}
+
+/// How a stream ended.
+enum End {
+    Complete,
+    // The server answered in place of the application.
+    Rejected(HttpStatus, String),
+    TimedOut(Timeout),
+    // The client reset the stream or the connection went away.
+    Reset,
+}
+
+/// Serves one request stream: reports it as a request and writes back the
+/// response the application sends for it.
+pub(super) async fn serve_stream(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    id: RequestId,
+    conn: Conn,
+) {
+    let (parts, mut recv) = request.into_parts();
+    let (method, uri, headers, meta) = match convert_head(&parts, conn.config.parsing_mode) {
+        Ok(head) => head,
+        Err(reason) => {
+            send_error(&mut respond, HttpStatus::BadRequest);
+            reject(&conn, None, HttpStatus::BadRequest, reason).await;
+            return;
+        }
+    };
+
+    // CONDITION
+    // IF Content-Length is more than MAX_PAYLOAD_SIZE.
+    if let Some(len) = meta.content_length {
+        if len > conn.config.max_payload_size {
+            send_error(&mut respond, HttpStatus::ContentTooLarge);
+            let reason = format!(
+                "Content-Length {} exceeds limit of {} bytes",
+                len, conn.config.max_payload_size
+            );
+            reject(&conn, None, HttpStatus::ContentTooLarge, reason).await;
+            return;
+        }
+    }
+
+    // CONDITION
+    // IF the client expects something other than 100-continue.
+    if meta.expect_unsupported {
+        send_error(&mut respond, HttpStatus::ExpectationFailed);
+        let reason = "Unsupported Expect header".to_string();
+        reject(&conn, None, HttpStatus::ExpectationFailed, reason).await;
+        return;
+    }
+
+    debug!(request = %id, stream = ?respond.stream_id(), "Request started");
+    let is_head = method == Method::Head;
+    let (resp_tx, mut resp_rx) = ResponseSender::channel();
+    let mut body_done = recv.is_end_stream();
+    // The client holds the body back until `100 Continue` is sent.
+    let mut expects_continue = meta.expect_continue && !body_done;
+    // Set when the application answered without asking for the body.
+    let mut body_skipped = false;
+    let _ = conn
+        .tx
+        .send(Event::RequestStart {
+            connection_id: conn.connection_id,
+            request_id: id,
+            client_addr: conn.client_addr.clone(),
+            method,
+            uri,
+            version: 2,
+            headers,
+            rest: Vec::new(),
+            more_body: !body_done,
+            meta,
+            resp_tx,
+        })
+        .await;
+
+    let started = Instant::now();
+    let mut last_read = Instant::now();
+    let mut received = 0usize;
+    let mut response = ResponseState::Pending;
+
+    let end = loop {
+        if body_done && matches!(response, ResponseState::Complete) {
+            break End::Complete;
+        }
+        if body_skipped && matches!(response, ResponseState::Complete) {
+            // Dropping the stream resets it with NO_ERROR once the response
+            // is flushed, telling the client to keep the body.
+            break End::Reset;
+        }
+        let deadline = if expects_continue {
+            Some((started + conn.config.response_timeout, Timeout::Response))
+        } else if !body_done {
+            Some((last_read + conn.config.body_read_timeout, Timeout::BodyRead))
+        } else if matches!(response, ResponseState::Pending) {
+            Some((started + conn.config.response_timeout, Timeout::Response))
+        } else {
+            None
+        };
+
+        tokio::select! {
+            // 1. Reading the body, returning flow-control credit once the
+            //    application has it.
+            data = recv.data(), if !body_done => {
+                let (data, end_of_data) = match data {
+                    Some(Ok(data)) => (data, false),
+                    Some(Err(e)) => {
+                        debug!(request = %id, "Request body failed: {}", e);
+                        break End::Reset;
+                    }
+                    // Trailers may follow; they are not reported.
+                    None => (Bytes::new(), true),
+                };
+                last_read = Instant::now();
+                expects_continue = false;
+                received += data.len();
+                // CONDITION
+                // IF the body grows past MAX_PAYLOAD_SIZE.
+                if received > conn.config.max_payload_size {
+                    break End::Rejected(
+                        HttpStatus::ContentTooLarge,
+                        format!("Body exceeds limit of {} bytes", conn.config.max_payload_size),
+                    );
+                }
+
+                body_done = end_of_data || recv.is_end_stream();
+                let len = data.len();
+                if len > 0 || body_done {
+                    let _ = conn
+                        .tx
+                        .send(Event::RequestBody {
+                            connection_id: conn.connection_id,
+                            request_id: id,
+                            body: data.to_vec(),
+                            more_body: !body_done,
+                        })
+                        .await;
+                }
+                let _ = recv.flow_control().release_capacity(len);
+            }
+            // 2. Writing the response.
+            message = resp_rx.recv(), if !matches!(response, ResponseState::Complete) => {
+                let Some(message) = message else {
+                    if matches!(response, ResponseState::Pending) {
+                        break End::Rejected(
+                            HttpStatus::InternalServerError,
+                            "Logic dropped resp_tx without responding".into(),
+                        );
+                    }
+                    warn!(request = %id, "Logic dropped resp_tx without finishing the response");
+                    break End::Reset;
+                };
+                if let ResponseMessage::Continue = message {
+                    if expects_continue && matches!(response, ResponseState::Pending) {
+                        expects_continue = false;
+                        last_read = Instant::now();
+                        let interim = http::Response::builder()
+                            .status(http::StatusCode::CONTINUE)
+                            .body(())
+                            .expect("static response head");
+                        if let Err(e) = respond.send_informational(interim) {
+                            debug!(request = %id, "Failed to send 100 Continue: {}", e);
+                            break End::Reset;
+                        }
+                    }
+                    continue;
+                }
+                body_skipped |= expects_continue;
+                expects_continue = false;
+                match write_message(&mut respond, &mut response, message, is_head).await {
+                    Ok(()) => {}
+                    Err(e) => {
+                        debug!(request = %id, "Failed to send response: {}", e);
+                        break End::Reset;
+                    }
+                }
+            }
+            // 3. The client giving up on the stream before any response.
+            reason = poll_fn(|cx| respond.poll_reset(cx)), if matches!(response, ResponseState::Pending) => {
+                debug!(request = %id, "Stream reset by client: {:?}", reason);
+                break End::Reset;
+            }
+            // 4. Giving up on a slow client or application.
+            _ = async {
+                match deadline {
+                    Some((at, _)) => sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                if let Some((_, timeout)) = deadline {
+                    break End::TimedOut(timeout);
+                }
+            }
+        }
+    };
+
+    match end {
+        End::Complete => {}
+        End::Rejected(status, reason) => {
+            abort(&mut respond, &mut response, status);
+            reject(&conn, Some(id), status, reason).await;
+        }
+        End::TimedOut(timeout) => {
+            warn!(request = %id, "Abandoning request: {} expired", timeout);
+            abort(&mut respond, &mut response, timeout.status());
+            let _ = conn
+                .tx
+                .send(Event::RequestTimeout {
+                    connection_id: conn.connection_id,
+                    request_id: Some(id),
+                    client_addr: conn.client_addr.clone(),
+                    timeout,
+                })
+                .await;
+        }
+        End::Reset => {
+            if let ResponseState::Streaming(Some(stream)) = &mut response {
+                stream.send_reset(Reason::CANCEL);
+            } else if matches!(response, ResponseState::Pending) {
+                respond.send_reset(Reason::CANCEL);
+            }
+            let _ = conn
+                .tx
+                .send(Event::Disconnect {
+                    connection_id: conn.connection_id,
+                    request_id: Some(id),
+                    client_addr: conn.client_addr.clone(),
+                })
+                .await;
+        }
+    }
+}
+
+/// Converts the request head into the types used by the HTTP/1.x parser.
+/// The `:authority` pseudo-header is reported as `Host`. Errors describe
+/// why the request cannot be served.
+fn convert_head(
+    parts: &http::request::Parts,
+    mode: ParsingMode,
+) -> Result<(Method, Uri, HeaderMap, RequestMeta), String> {
+    let invalid_target = || "invalid request target".to_string();
+    let method = Method::parse(parts.method.as_str());
+    let target = match parts.uri.path_and_query() {
+        Some(path) => path.as_str().to_string(),
+        // CONNECT requests name an authority only.
+        None => parts
+            .uri
+            .authority()
+            .ok_or_else(invalid_target)?
+            .as_str()
+            .to_string(),
+    };
+    let uri = Uri::parse(&target).map_err(|_| invalid_target())?;
+
+    let mut headers = HeaderMap::new();
+    if !parts.headers.contains_key(http::header::HOST) {
+        if let Some(authority) = parts.uri.authority() {
+            headers
+                .append("host", authority.as_str())
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    for (name, value) in &parts.headers {
+        headers
+            .append(name.as_str(), value.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+
+    let fields: Vec<httparse::Header<'_>> = headers
+        .iter()
+        .map(|(name, value)| httparse::Header { name, value })
+        .collect();
+    let meta = RequestMeta::from_headers(&fields, mode).map_err(|e| e.to_string())?;
+    Ok((method, uri, headers, meta))
+}
+
+/// Handles one message from the application.
+async fn write_message(
+    respond: &mut SendResponse<Bytes>,
+    response: &mut ResponseState,
+    message: ResponseMessage,
+    is_head: bool,
+) -> Result<(), h2::Error> {
+    match (message, &mut *response) {
+        (ResponseMessage::Start(head), ResponseState::Pending) => {
+            let send_body = !is_head && head.status.allows_body();
+            let stream = respond.send_response(convert_response(&head), !send_body)?;
+            *response = ResponseState::Streaming(send_body.then_some(stream));
+        }
+        (ResponseMessage::Body { data, more_body }, ResponseState::Streaming(stream)) => {
+            if let Some(stream) = stream {
+                send_data(stream, Bytes::from(data), !more_body).await?;
+            }
+            if !more_body {
+                *response = ResponseState::Complete;
+            }
+        }
+        // Answered by `serve_stream`.
+        (ResponseMessage::Continue, _) => {}
+        (ResponseMessage::Start(_), _) => {
+            warn!("Response started twice");
+            return Err(Reason::INTERNAL_ERROR.into());
+        }
+        (ResponseMessage::Body { .. }, _) => {
+            warn!("Response body sent before start");
+            return Err(Reason::INTERNAL_ERROR.into());
+        }
+    }
+    Ok(())
+}
+
+/// Sends `data` as the peer's flow-control window allows.
+async fn send_data(
+    stream: &mut SendStream<Bytes>,
+    mut data: Bytes,
+    end: bool,
+) -> Result<(), h2::Error> {
+    if data.is_empty() {
+        return stream.send_data(data, end);
+    }
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+        let granted = match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(granted) => granted?,
+            None => return Err(Reason::STREAM_CLOSED.into()),
+        };
+        if granted == 0 {
+            continue;
+        }
+        let chunk = data.split_to(granted.min(data.len()));
+        stream.send_data(chunk, end && data.is_empty())?;
+    }
+    Ok(())
+}
+
+/// Builds the head of an HTTP/2 response, leaving out headers that only
+/// make sense on an HTTP/1.x connection.
+fn convert_response(head: &Response) -> http::Response<()> {
+    let mut builder = http::Response::builder().status(head.status.code());
+    for (name, value) in head.headers.iter() {
+        let lower = name.to_ascii_lowercase();
+        if CONNECTION_HEADERS.contains(&lower.as_str()) {
+            continue;
+        }
+        builder = builder.header(lower, value);
+    }
+    builder.body(()).unwrap_or_else(|e| {
+        warn!("Invalid response head: {}", e);
+        let mut response = http::Response::new(());
+        *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
+/// Answers with `status` if nothing was sent yet, otherwise resets the
+/// stream.
+fn abort(respond: &mut SendResponse<Bytes>, response: &mut ResponseState, status: HttpStatus) {
+    match response {
+        ResponseState::Pending => send_error(respond, status),
+        ResponseState::Streaming(Some(stream)) => stream.send_reset(Reason::CANCEL),
+        ResponseState::Streaming(None) | ResponseState::Complete => {
+            respond.send_reset(Reason::CANCEL)
+        }
+    }
+}
+
+/// Sends `Response::error(status)` and ends the stream.
+fn send_error(respond: &mut SendResponse<Bytes>, status: HttpStatus) {
+    let error = Response::error(status);
+    let body = Bytes::from(error.body.clone());
+    if let Ok(mut stream) = respond.send_response(convert_response(&error), false) {
+        // Error bodies are far below the initial window.
+        let _ = stream.send_data(body, true);
+    }
+}
+
+async fn reject(conn: &Conn, request_id: Option<RequestId>, status: HttpStatus, reason: String) {
+    warn!(status = status.code(), "Rejecting request: {}", reason);
+    let _ = conn
+        .tx
+        .send(Event::RequestRejected {
+            connection_id: conn.connection_id,
+            request_id,
+            client_addr: conn.client_addr.clone(),
+            status,
+            reason,
+        })
+        .await;
+}
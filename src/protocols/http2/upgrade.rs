@@ -0,0 +1,245 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::core::headers::HeaderMap;
+use crate::core::structs::RequestMeta;
+use crate::core::uri::Uri;
+
+use super::rewind::Rewind;
+
+/// First bytes a client sends on an HTTP/2 connection.
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame header: 24-bit length, type, flags, 31-bit stream id.
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+// Largest frame payload a peer must accept before settings say otherwise.
+const MAX_FRAME_SIZE: usize = 16_384;
+
+// Hop-by-hop headers that have no place in an HTTP/2 header block.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "http2-settings",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Whether `buf` could still turn out to be the connection preface.
+pub(crate) fn is_preface_start(buf: &[u8]) -> bool {
+    !buf.is_empty() && buf.iter().zip(PREFACE).all(|(a, b)| a == b)
+}
+
+/// Checks an HTTP/1.1 request for `Upgrade: h2c`. When the upgrade can be
+/// done, returns the request re-encoded as the HEADERS frame of stream 1.
+///
+/// Requests with a body are served over HTTP/1.1, as are those whose
+/// header block would not fit in a single frame.
+pub(crate) fn upgrade_frame(
+    method: &str,
+    uri: &Uri,
+    version: u8,
+    headers: &HeaderMap,
+    meta: &RequestMeta,
+) -> Option<Vec<u8>> {
+    // CONDITION
+    // IF the client asked for h2c on an HTTP/1.1 request without a body.
+    if version != 1
+        || !headers.has_token("Upgrade", "h2c")
+        || !headers.has_token("Connection", "upgrade")
+        || !headers.contains("HTTP2-Settings")
+        || meta.is_chunked
+        || meta.content_length.unwrap_or(0) > 0
+    {
+        return None;
+    }
+
+    let path = if uri.raw.starts_with('/') {
+        uri.raw.clone()
+    } else {
+        match &uri.query {
+            Some(query) => format!("{}?{}", uri.path, query),
+            None => uri.path.clone(),
+        }
+    };
+    let authority = headers
+        .get("Host")
+        .map(<[u8]>::to_vec)
+        .or_else(|| uri.authority.as_ref().map(|a| a.as_bytes().to_vec()))
+        .unwrap_or_default();
+
+    let mut block = Vec::new();
+    encode_field(&mut block, b":method", method.as_bytes());
+    encode_field(&mut block, b":scheme", b"http");
+    encode_field(&mut block, b":path", path.as_bytes());
+    encode_field(&mut block, b":authority", &authority);
+    for (name, value) in headers.iter() {
+        let name = name.to_ascii_lowercase();
+        if name == "host" || CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if name == "te" && !value.eq_ignore_ascii_case(b"trailers") {
+            continue;
+        }
+        encode_field(&mut block, name.as_bytes(), value);
+    }
+    if block.len() > MAX_FRAME_SIZE {
+        return None;
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + block.len());
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    frame.push(FRAME_HEADERS);
+    frame.push(FLAG_END_STREAM | FLAG_END_HEADERS);
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend_from_slice(&block);
+    Some(frame)
+}
+
+/// Waits for the client preface and its SETTINGS frame, then queues
+/// `frame` right behind them, as if the client had sent the upgraded
+/// request on stream 1 itself.
+pub(super) async fn splice(io: &mut Rewind, frame: &[u8]) -> Result<()> {
+    let settings_at = PREFACE.len();
+    loop {
+        let buffered = io.buffered();
+        if !buffered.is_empty() && !is_preface_start(buffered) {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid HTTP/2 preface"));
+        }
+        if buffered.len() >= settings_at + FRAME_HEADER_LEN {
+            let header = &buffered[settings_at..settings_at + FRAME_HEADER_LEN];
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            if header[3] != FRAME_SETTINGS || length > MAX_FRAME_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "HTTP/2 preface not followed by SETTINGS",
+                ));
+            }
+            let end = settings_at + FRAME_HEADER_LEN + length;
+            if buffered.len() >= end {
+                io.insert(end, frame);
+                return Ok(());
+            }
+        }
+        if io.fill().await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+/// Appends a literal header field without indexing and without Huffman
+/// coding (RFC 7541, section 6.2.2).
+fn encode_field(out: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    out.push(0x00);
+    encode_string(out, name);
+    encode_string(out, value);
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &[u8]) {
+    encode_int(out, s.len(), 7);
+    out.extend_from_slice(s);
+}
+
+/// Integer with an N-bit prefix (RFC 7541, section 5.1). The flag bits
+/// above the prefix are left clear.
+fn encode_int(out: &mut Vec<u8>, value: usize, prefix_bits: u32) {
+    let max = (1usize << prefix_bits) - 1;
+    if value < max {
+        out.push(value as u8);
+        return;
+    }
+    out.push(max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(name, value).unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn test_is_preface_start() {
+        assert!(is_preface_start(b"PRI * HT"));
+        assert!(is_preface_start(PREFACE));
+        assert!(is_preface_start(&[PREFACE, b"\0\0"].concat()));
+        assert!(!is_preface_start(b""));
+        assert!(!is_preface_start(b"POST / HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_encode_int() {
+        // Examples from RFC 7541, appendix C.1.
+        let mut out = Vec::new();
+        encode_int(&mut out, 10, 5);
+        assert_eq!(out, [10]);
+
+        out.clear();
+        encode_int(&mut out, 1337, 5);
+        assert_eq!(out, [31, 154, 10]);
+    }
+
+    #[test]
+    fn test_upgrade_frame() {
+        let uri = Uri::parse("/a?b=1").unwrap();
+        let map = headers(&[
+            ("Host", "example.com"),
+            ("Connection", "Upgrade, HTTP2-Settings"),
+            ("Upgrade", "h2c"),
+            ("HTTP2-Settings", "AAMAAABkAAQAAP__"),
+            ("Accept", "*/*"),
+        ]);
+        let meta = RequestMeta::new();
+        let frame = upgrade_frame("GET", &uri, 1, &map, &meta).unwrap();
+
+        assert_eq!(frame[3], FRAME_HEADERS);
+        assert_eq!(frame[4], FLAG_END_STREAM | FLAG_END_HEADERS);
+        assert_eq!(&frame[5..9], &[0, 0, 0, 1]);
+        let block = &frame[9..];
+        assert_eq!(
+            block.len(),
+            u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize
+        );
+        assert!(block.windows(6).any(|w| w == b"/a?b=1"));
+        assert!(block.windows(11).any(|w| w == b"example.com"));
+        assert!(block.windows(6).any(|w| w == b"accept"));
+        assert!(!block.windows(7).any(|w| w == b"upgrade"));
+    }
+
+    #[test]
+    fn test_upgrade_frame_declines() {
+        let uri = Uri::parse("/").unwrap();
+        let upgrade = [
+            ("Connection", "Upgrade, HTTP2-Settings"),
+            ("Upgrade", "h2c"),
+            ("HTTP2-Settings", ""),
+        ];
+        let meta = RequestMeta::new();
+
+        // Not asked for.
+        assert!(upgrade_frame("GET", &uri, 1, &headers(&upgrade[..2]), &meta).is_none());
+        assert!(upgrade_frame("GET", &uri, 1, &headers(&[("Upgrade", "h2c")]), &meta).is_none());
+        // HTTP/1.0.
+        assert!(upgrade_frame("GET", &uri, 0, &headers(&upgrade), &meta).is_none());
+        // With a body.
+        let with_body = RequestMeta {
+            content_length: Some(3),
+            ..RequestMeta::new()
+        };
+        assert!(upgrade_frame("POST", &uri, 1, &headers(&upgrade), &with_body).is_none());
+    }
+}
@@ -1 +1,6 @@
+#[cfg(feature = "http2")]
+pub mod http2;
 pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod websocket;
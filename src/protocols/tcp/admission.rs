@@ -0,0 +1,98 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+use crate::core::enums::{HttpStatus, LimitPolicy};
+use crate::core::handle::ServerHandle;
+use crate::core::response::Response;
+use crate::core::structs::ServerConfig;
+
+use super::connection::ByteStream;
+
+/// Enforces `max_connections` and `max_connections_per_ip`.
+pub(crate) struct Admission {
+    total: Option<Arc<Semaphore>>,
+    per_ip: Option<usize>,
+    policy: LimitPolicy,
+    handle: ServerHandle,
+}
+
+/// A connection's place under the limits, given back on drop.
+pub(crate) struct Admitted {
+    handle: ServerHandle,
+    ip: Option<IpAddr>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        self.handle.stats().close(self.ip);
+    }
+}
+
+impl Admission {
+    pub(crate) fn new(config: &ServerConfig, handle: ServerHandle) -> Self {
+        Self {
+            total: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            per_ip: config.max_connections_per_ip,
+            policy: config.connection_limit_policy,
+            handle,
+        }
+    }
+
+    /// Under `LimitPolicy::Wait`, waits for room under `max_connections`
+    /// before the next accept, so excess clients queue in the listen
+    /// backlog rather than in memory.
+    pub(crate) async fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        match (&self.total, self.policy) {
+            (Some(total), LimitPolicy::Wait) => Arc::clone(total).acquire_owned().await.ok(),
+            _ => None,
+        }
+    }
+
+    /// Admits a connection from `ip` (`None` for Unix socket peers), waiting for room under the per-IP
+    /// cap under `LimitPolicy::Wait`. `None` means it has to be refused.
+    pub(crate) async fn admit(
+        &self,
+        ip: Option<IpAddr>,
+        reserved: Option<OwnedSemaphorePermit>,
+    ) -> Option<Admitted> {
+        let permit = match (&self.total, reserved) {
+            (_, Some(permit)) => Some(permit),
+            (Some(total), None) => Some(Arc::clone(total).try_acquire_owned().ok()?),
+            (None, None) => None,
+        };
+
+        let stats = self.handle.stats();
+        if self.policy == LimitPolicy::Wait {
+            stats.open(ip, self.per_ip).await;
+        } else if !stats.try_open(ip, self.per_ip) {
+            return None;
+        }
+
+        Some(Admitted {
+            handle: self.handle.clone(),
+            ip,
+            _permit: permit,
+        })
+    }
+
+    /// Turns away a connection that exceeds a limit.
+    pub(crate) async fn refuse(&self, stream: &mut Box<dyn ByteStream>) {
+        self.handle.stats().record_refused();
+        debug!(policy = ?self.policy, "Connection limit reached, refusing connection");
+
+        if self.policy == LimitPolicy::Reject {
+            let data = Response::error(HttpStatus::ServiceUnavailable)
+                .header("Connection", "close")
+                .build();
+            let _ = stream.write_all(&data).await;
+            let _ = stream.flush().await;
+        }
+        stream.close().await;
+    }
+}
@@ -0,0 +1,459 @@
+use std::fmt;
+
+use crate::core::enums::ParsingMode;
+
+// Upper bound for a chunk-size line including extensions, and for the
+// trailer section. Both are metadata and never legitimately large.
+const MAX_LINE_LENGTH: usize = 4096;
+const MAX_TRAILER_LENGTH: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkedError {
+    InvalidChunkSize,
+    InvalidLineEnding,
+    ControlCharacter,
+    LineTooLong,
+    TrailersTooLarge,
+    PayloadTooLarge { limit: usize },
+}
+
+impl fmt::Display for ChunkedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidChunkSize => write!(f, "invalid chunk size"),
+            Self::InvalidLineEnding => write!(f, "invalid line ending in chunked body"),
+            Self::ControlCharacter => write!(f, "control character in chunked body"),
+            Self::LineTooLong => write!(f, "chunk size line too long"),
+            Self::TrailersTooLarge => write!(f, "chunked trailers too large"),
+            Self::PayloadTooLarge { limit } => {
+                write!(f, "decoded body exceeds {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChunkedError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Hex digits of the chunk size.
+    Size,
+    // Whitespace after the size, allowed only in front of a `;`.
+    SizeWhitespace,
+    // `;name=value` extensions after the size, ignored.
+    Extension,
+    SizeLf,
+    Data(usize),
+    DataCr,
+    DataLf,
+    // Start of a trailer line or the final CRLF.
+    TrailerStart,
+    Trailer,
+    TrailerLf,
+    EndLf,
+    Done,
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` bodies.
+///
+/// Input can be fed in arbitrary slices; decoded data is appended to the
+/// caller's buffer. Chunk extensions and trailer fields are consumed and
+/// discarded.
+///
+/// In `ParsingMode::Strict` (the default) bare LF line endings, control
+/// characters in size, extension and trailer lines, and whitespace after
+/// the size that doesn't lead to an extension are rejected. Lenient mode
+/// ends lines at a bare LF and ignores the rest.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    mode: ParsingMode,
+    state: State,
+    chunk_size: usize,
+    line_length: usize,
+    trailer_length: usize,
+    decoded: usize,
+    limit: usize,
+}
+
+impl ChunkedDecoder {
+    /// Creates a decoder that fails once more than `limit` bytes of
+    /// payload have been decoded.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            mode: ParsingMode::default(),
+            state: State::Size,
+            chunk_size: 0,
+            line_length: 0,
+            trailer_length: 0,
+            decoded: 0,
+            limit,
+        }
+    }
+
+    // Builder-pattern
+    pub fn mode(mut self, mode: ParsingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Total number of payload bytes decoded so far.
+    pub fn decoded(&self) -> usize {
+        self.decoded
+    }
+
+    /// Decodes as much of `input` as possible into `out`.
+    /// Returns the number of input bytes consumed; anything after the
+    /// terminating chunk is left untouched.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize, ChunkedError> {
+        let mut pos = 0;
+
+        while pos < input.len() {
+            let byte = input[pos];
+            match self.state {
+                State::Size => {
+                    self.bump_line()?;
+                    match byte {
+                        b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F' => {
+                            let digit = (byte as char).to_digit(16).unwrap_or(0) as usize;
+                            self.chunk_size = self
+                                .chunk_size
+                                .checked_mul(16)
+                                .and_then(|size| size.checked_add(digit))
+                                .ok_or(ChunkedError::InvalidChunkSize)?;
+                        }
+                        _ if self.line_length == 1 => {
+                            return Err(ChunkedError::InvalidChunkSize);
+                        }
+                        b';' => self.state = State::Extension,
+                        b' ' | b'\t' if self.is_strict() => self.state = State::SizeWhitespace,
+                        b' ' | b'\t' => self.state = State::Extension,
+                        b'\r' => self.state = State::SizeLf,
+                        b'\n' => self.end_size_line(true)?,
+                        _ => return Err(ChunkedError::InvalidChunkSize),
+                    }
+                }
+                State::SizeWhitespace => {
+                    self.bump_line()?;
+                    match byte {
+                        b' ' | b'\t' => {}
+                        b';' => self.state = State::Extension,
+                        _ => return Err(ChunkedError::InvalidChunkSize),
+                    }
+                }
+                State::Extension => {
+                    self.bump_line()?;
+                    match byte {
+                        b'\r' => self.state = State::SizeLf,
+                        b'\n' => self.end_size_line(true)?,
+                        _ => self.check_text(byte)?,
+                    }
+                }
+                State::SizeLf => {
+                    if byte != b'\n' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+                    self.end_size_line(false)?;
+                }
+                State::Data(remaining) => {
+                    let take = remaining.min(input.len() - pos);
+                    out.extend_from_slice(&input[pos..pos + take]);
+                    self.decoded += take;
+                    pos += take;
+                    self.state = if take == remaining {
+                        State::DataCr
+                    } else {
+                        State::Data(remaining - take)
+                    };
+                    continue;
+                }
+                State::DataCr => {
+                    if byte != b'\r' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+                    self.state = State::DataLf;
+                }
+                State::DataLf => {
+                    if byte != b'\n' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+                    self.state = State::Size;
+                }
+                State::TrailerStart => {
+                    self.bump_trailer()?;
+                    match byte {
+                        b'\r' => self.state = State::EndLf,
+                        b'\n' => {
+                            self.check_bare_lf()?;
+                            self.state = State::Done;
+                            return Ok(pos + 1);
+                        }
+                        _ => {
+                            self.check_text(byte)?;
+                            self.state = State::Trailer;
+                        }
+                    }
+                }
+                State::Trailer => {
+                    self.bump_trailer()?;
+                    match byte {
+                        b'\r' => self.state = State::TrailerLf,
+                        b'\n' => {
+                            self.check_bare_lf()?;
+                            self.state = State::TrailerStart;
+                        }
+                        _ => self.check_text(byte)?,
+                    }
+                }
+                State::TrailerLf => {
+                    if byte != b'\n' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+                    self.state = State::TrailerStart;
+                }
+                State::EndLf => {
+                    if byte != b'\n' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+                    self.state = State::Done;
+                    return Ok(pos + 1);
+                }
+                State::Done => return Ok(pos),
+            }
+            pos += 1;
+        }
+
+        Ok(pos)
+    }
+
+    /// Finishes a chunk-size line, moving on to its data or, after the
+    /// last chunk, to the trailers.
+    fn end_size_line(&mut self, bare_lf: bool) -> Result<(), ChunkedError> {
+        if bare_lf {
+            self.check_bare_lf()?;
+        }
+        self.line_length = 0;
+        if self.chunk_size == 0 {
+            self.state = State::TrailerStart;
+            return Ok(());
+        }
+
+        // CONDITION
+        // If the decoded body would be bigger than the limit.
+        // `decoded` never exceeds `limit`, so this can't
+        // overflow the way adding the chunk size could.
+        if self.chunk_size > self.limit - self.decoded {
+            return Err(ChunkedError::PayloadTooLarge { limit: self.limit });
+        }
+        self.state = State::Data(self.chunk_size);
+        self.chunk_size = 0;
+        Ok(())
+    }
+
+    fn is_strict(&self) -> bool {
+        self.mode == ParsingMode::Strict
+    }
+
+    fn check_bare_lf(&self) -> Result<(), ChunkedError> {
+        if self.is_strict() {
+            return Err(ChunkedError::InvalidLineEnding);
+        }
+        Ok(())
+    }
+
+    /// Control characters other than HTAB have no place in a line.
+    fn check_text(&self, byte: u8) -> Result<(), ChunkedError> {
+        if self.is_strict() && ((byte < 0x20 && byte != b'\t') || byte == 0x7f) {
+            return Err(ChunkedError::ControlCharacter);
+        }
+        Ok(())
+    }
+
+    fn bump_line(&mut self) -> Result<(), ChunkedError> {
+        self.line_length += 1;
+        if self.line_length > MAX_LINE_LENGTH {
+            return Err(ChunkedError::LineTooLong);
+        }
+        Ok(())
+    }
+
+    fn bump_trailer(&mut self) -> Result<(), ChunkedError> {
+        self.trailer_length += 1;
+        if self.trailer_length > MAX_TRAILER_LENGTH {
+            return Err(ChunkedError::TrailersTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// Terminating chunk of a body without trailers.
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// Appends `data` to `out` framed as a single chunk. Empty data is skipped
+/// since a zero-sized chunk would end the body.
+pub fn encode_chunk(data: &[u8], out: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    out.extend_from_slice(format!("{:X}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(input: &[u8]) -> Result<(Vec<u8>, usize), ChunkedError> {
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut out = Vec::new();
+        let consumed = decoder.decode(input, &mut out)?;
+        assert!(decoder.is_done());
+        Ok((out, consumed))
+    }
+
+    #[test]
+    fn test_single_chunk() {
+        let (out, consumed) = decode_all(b"5\r\nhello\r\n0\r\n\r\n").unwrap();
+        assert_eq!(out, b"hello");
+        assert_eq!(consumed, 15);
+    }
+
+    #[test]
+    fn test_multiple_chunks_and_hex_sizes() {
+        let (out, _) = decode_all(b"3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\n").unwrap();
+        assert_eq!(out, b"abc0123456789");
+    }
+
+    #[test]
+    fn test_extensions_and_trailers() {
+        let raw = b"4;name=value\r\nWiki\r\n0;last\r\nExpires: never\r\nX-Sum: 1\r\n\r\n";
+        let (out, consumed) = decode_all(raw).unwrap();
+        assert_eq!(out, b"Wiki");
+        assert_eq!(consumed, raw.len());
+    }
+
+    #[test]
+    fn test_leaves_pipelined_bytes() {
+        let raw = b"1\r\na\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let (_, consumed) = decode_all(raw).unwrap();
+        assert_eq!(&raw[consumed..], b"GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn test_byte_by_byte() {
+        let raw = b"6\r\nchunks\r\n2;x=y\r\n!!\r\n0\r\nTrailer: 1\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut out = Vec::new();
+
+        for byte in raw.iter() {
+            let consumed = decoder
+                .decode(std::slice::from_ref(byte), &mut out)
+                .unwrap();
+            assert_eq!(consumed, 1);
+        }
+
+        assert!(decoder.is_done());
+        assert_eq!(out, b"chunks!!");
+        assert_eq!(decoder.decoded(), 8);
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        assert_eq!(
+            decode_all(b"zz\r\n").unwrap_err(),
+            ChunkedError::InvalidChunkSize
+        );
+        assert_eq!(
+            decode_all(b"\r\n").unwrap_err(),
+            ChunkedError::InvalidChunkSize
+        );
+        assert_eq!(
+            decode_all(b"ffffffffffffffffffff\r\n").unwrap_err(),
+            ChunkedError::InvalidChunkSize
+        );
+    }
+
+    #[test]
+    fn test_strict_line_framing() {
+        let rejected: [(&[u8], ChunkedError); 7] = [
+            (b"3;\nabc\r\n0\r\n\r\n", ChunkedError::InvalidLineEnding),
+            (b"3\nabc\r\n0\r\n\r\n", ChunkedError::InvalidLineEnding),
+            (b"5 junk\r\n", ChunkedError::InvalidChunkSize),
+            (b"5 \r\n", ChunkedError::InvalidChunkSize),
+            (b"3;a\x00b\r\n", ChunkedError::ControlCharacter),
+            (b"0\r\nX-A: 1\n\r\n", ChunkedError::InvalidLineEnding),
+            (b"0\r\nX-A: \x7f\r\n\r\n", ChunkedError::ControlCharacter),
+        ];
+        for (raw, error) in rejected {
+            assert_eq!(decode_all(raw).unwrap_err(), error, "{:?}", raw);
+        }
+
+        // Whitespace in front of an extension is allowed.
+        let (out, _) = decode_all(b"3 \t;x=1\r\nabc\r\n0\r\n\r\n").unwrap();
+        assert_eq!(out, b"abc");
+    }
+
+    #[test]
+    fn test_lenient_line_framing() {
+        let mut decoder = ChunkedDecoder::new(1024).mode(ParsingMode::Lenient);
+        let mut out = Vec::new();
+        let raw = b"3;\nabc\r\n2 junk\r\nde\r\n0\nX-A: 1\n\n";
+
+        assert_eq!(decoder.decode(raw, &mut out).unwrap(), raw.len());
+        assert!(decoder.is_done());
+        assert_eq!(out, b"abcde");
+    }
+
+    #[test]
+    fn test_missing_crlf_after_data() {
+        assert_eq!(
+            decode_all(b"3\r\nabcX").unwrap_err(),
+            ChunkedError::InvalidLineEnding
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut encoded = Vec::new();
+        encode_chunk(b"hello ", &mut encoded);
+        encode_chunk(b"", &mut encoded);
+        encode_chunk(&[b'x'; 26], &mut encoded);
+        encoded.extend_from_slice(LAST_CHUNK);
+
+        assert!(encoded.starts_with(b"6\r\nhello \r\n1A\r\n"));
+
+        let (out, consumed) = decode_all(&encoded).unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(&out[..6], b"hello ");
+        assert_eq!(out.len(), 32);
+    }
+
+    #[test]
+    fn test_limit_enforced_on_decoded_size() {
+        let mut decoder = ChunkedDecoder::new(4);
+        let mut out = Vec::new();
+
+        assert!(decoder.decode(b"3\r\nabc\r\n", &mut out).is_ok());
+        assert_eq!(
+            decoder.decode(b"2\r\nde\r\n", &mut out).unwrap_err(),
+            ChunkedError::PayloadTooLarge { limit: 4 }
+        );
+    }
+
+    #[test]
+    fn test_huge_chunk_size_hits_the_limit() {
+        let mut decoder = ChunkedDecoder::new(4);
+        let mut out = Vec::new();
+
+        assert!(decoder.decode(b"3\r\nabc\r\n", &mut out).is_ok());
+        let huge = format!("{:x}\r\n", usize::MAX);
+        assert_eq!(
+            decoder.decode(huge.as_bytes(), &mut out).unwrap_err(),
+            ChunkedError::PayloadTooLarge { limit: 4 }
+        );
+    }
+}
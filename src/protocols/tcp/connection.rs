@@ -20,6 +20,11 @@ pub trait ByteStream: AsyncRead + AsyncWrite + Send + Unpin {
     async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
     async fn write(&mut self, data: &[u8]) -> std::io::Result<()>;
     async fn close(&mut self) -> ();
+
+    /// Protocol agreed on through TLS ALPN, if any.
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
 }
 impl AsyncRead for TcpByteStream {
     fn poll_read(
@@ -63,3 +68,71 @@ impl ByteStream for TcpByteStream {
         let _ = self.stream.shutdown().await;
     }
 }
+
+#[cfg(unix)]
+pub use unix::UnixByteStream;
+
+#[cfg(unix)]
+mod unix {
+    use async_trait::async_trait;
+    use std::io::Result;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    use super::ByteStream;
+
+    pub struct UnixByteStream {
+        stream: UnixStream,
+    }
+
+    impl UnixByteStream {
+        pub fn new(stream: UnixStream) -> Self {
+            Self { stream }
+        }
+    }
+
+    impl AsyncRead for UnixByteStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<Result<()>> {
+            Pin::new(&mut self.stream).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for UnixByteStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Pin::new(&mut self.stream).poll_shutdown(cx)
+        }
+    }
+
+    #[async_trait]
+    impl ByteStream for UnixByteStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.stream.read(buf).await
+        }
+
+        async fn write(&mut self, data: &[u8]) -> Result<()> {
+            self.stream.write_all(data).await
+        }
+
+        async fn close(&mut self) -> () {
+            let _ = self.stream.shutdown().await;
+        }
+    }
+}
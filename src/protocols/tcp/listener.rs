@@ -2,6 +2,8 @@ use async_trait::async_trait;
 use std::io::Result;
 use std::net::SocketAddr;
 
+use crate::core::addr::PeerAddr;
+
 use super::connection::{ByteStream, TcpByteStream};
 
 pub struct TcpByteListener {
@@ -10,7 +12,7 @@ pub struct TcpByteListener {
 
 #[async_trait]
 pub trait Listener: Send + Sync {
-    async fn accept(&self) -> Result<(Box<dyn ByteStream>, SocketAddr)>;
+    async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)>;
 }
 
 impl TcpByteListener {
@@ -18,15 +20,256 @@ impl TcpByteListener {
         let listener = tokio::net::TcpListener::bind(addr).await?;
         Ok(Self { inner: listener })
     }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[async_trait]
+impl Listener for Box<dyn Listener> {
+    async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)> {
+        (**self).accept().await
+    }
 }
 
 #[async_trait]
 impl Listener for TcpByteListener {
-    async fn accept(&self) -> Result<(Box<dyn ByteStream>, SocketAddr)> {
+    async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)> {
         let (stream, addr) = self.inner.accept().await?;
 
         let byte_stream: Box<dyn ByteStream> = Box::new(TcpByteStream::new(stream));
 
-        Ok((byte_stream, addr))
+        Ok((byte_stream, PeerAddr::Tcp(addr)))
+    }
+}
+
+#[cfg(unix)]
+pub use unix::UnixByteListener;
+
+#[cfg(unix)]
+mod unix {
+    use async_trait::async_trait;
+    use std::collections::hash_map::RandomState;
+    use std::ffi::OsStr;
+    use std::fs::{self, Permissions};
+    use std::hash::BuildHasher;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+
+    use crate::core::addr::PeerAddr;
+
+    use super::super::connection::{ByteStream, UnixByteStream};
+    use super::Listener;
+
+    /// Listener on a Unix domain socket. The socket file is removed when
+    /// the listener is dropped.
+    pub struct UnixByteListener {
+        inner: tokio::net::UnixListener,
+        path: PathBuf,
+    }
+
+    impl UnixByteListener {
+        /// Binds a socket at `path`, replacing a socket file left behind by
+        /// a server that is gone. `mode` sets the file permissions, such as
+        /// `0o660` to admit a group.
+        pub async fn bind(path: impl AsRef<Path>, mode: Option<u32>) -> Result<Self> {
+            let path = path.as_ref();
+            remove_stale_socket(path).await?;
+
+            let inner = match mode {
+                Some(mode) => bind_with_mode(path, mode)?,
+                None => tokio::net::UnixListener::bind(path)?,
+            };
+            Ok(Self {
+                inner,
+                path: path.to_path_buf(),
+            })
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Drop for UnixByteListener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    #[async_trait]
+    impl Listener for UnixByteListener {
+        async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)> {
+            let (stream, addr) = self.inner.accept().await?;
+
+            let byte_stream: Box<dyn ByteStream> = Box::new(UnixByteStream::new(stream));
+            let peer = PeerAddr::Unix(addr.as_pathname().map(Path::to_path_buf));
+
+            Ok((byte_stream, peer))
+        }
+    }
+
+    /// Binds in a private directory next to `path` and links the socket into
+    /// place once it has `mode`, so nobody can connect while it still has
+    /// the umask's permissions. Unlike a rename, the link fails if
+    /// something appeared at `path` in the meantime.
+    fn bind_with_mode(path: &Path, mode: u32) -> Result<tokio::net::UnixListener> {
+        let name = path.file_name().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} has no file name", path.display()),
+            )
+        })?;
+        let dir = create_staging_dir(path, name)?;
+
+        let staged = dir.join(name);
+        let result = tokio::net::UnixListener::bind(&staged).and_then(|inner| {
+            fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+            fs::hard_link(&staged, path).map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} was created while binding", path.display()),
+                ),
+                _ => e,
+            })?;
+            Ok(inner)
+        });
+        let _ = fs::remove_file(&staged);
+        let _ = fs::remove_dir(&dir);
+        result
+    }
+
+    /// Creates a `0o700` directory with a random name next to `path`, so a
+    /// directory left behind by a crashed process never gets in the way.
+    fn create_staging_dir(path: &Path, name: &OsStr) -> Result<PathBuf> {
+        // 1. A few tries, in case a name is taken
+        for _ in 0..8 {
+            let nonce = RandomState::new().hash_one(std::process::id());
+            let dir = path.with_file_name(format!(".{}.{:016x}", name.to_string_lossy(), nonce));
+            match fs::DirBuilder::new().mode(0o700).create(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("no free staging directory next to {}", path.display()),
+        ))
+    }
+
+    /// Removes the socket file at `path` if nothing listens on it anymore.
+    /// Anything else at `path` is left alone and reported as an error.
+    async fn remove_stale_socket(path: &Path) -> Result<()> {
+        // 1. Nothing there
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        // 2. Never delete a file that is not a socket
+        if !metadata.file_type().is_socket() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+
+        // 3. A socket nobody accepts on is stale
+        match tokio::net::UnixStream::connect(path).await {
+            Ok(_) => Err(Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path.display()),
+            )),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        fn socket_path(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("aegis-{}-{}.sock", name, std::process::id()))
+        }
+
+        #[tokio::test]
+        async fn test_accepts_and_cleans_up() {
+            let path = socket_path("accept");
+            let listener = UnixByteListener::bind(&path, Some(0o600)).await.unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            // The private directory it was bound in is gone.
+            let staging = format!(".aegis-accept-{}.sock.", std::process::id());
+            let leftovers = fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.file_name().to_string_lossy().starts_with(&staging));
+            assert!(!leftovers);
+
+            let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            let (mut stream, peer) = listener.accept().await.unwrap();
+            assert_eq!(peer, PeerAddr::Unix(None));
+
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+
+            drop(listener);
+            assert!(!path.exists());
+        }
+
+        #[tokio::test]
+        async fn test_replaces_stale_socket() {
+            let path = socket_path("stale");
+            let _ = fs::remove_file(&path);
+            // A std listener dropped without cleanup leaves its file behind.
+            drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+            assert!(path.exists());
+
+            let listener = UnixByteListener::bind(&path, None).await.unwrap();
+            assert!(tokio::net::UnixStream::connect(&path).await.is_ok());
+            drop(listener);
+        }
+
+        #[tokio::test]
+        async fn test_refuses_live_socket_and_other_files() {
+            let path = socket_path("live");
+            let listener = UnixByteListener::bind(&path, None).await.unwrap();
+            let err = UnixByteListener::bind(&path, None).await.err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::AddrInUse);
+            drop(listener);
+
+            fs::write(&path, b"not a socket").unwrap();
+            let err = UnixByteListener::bind(&path, None).await.err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+            assert!(path.exists());
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_bind_with_mode_never_replaces_a_file() {
+            let path = socket_path("appeared");
+            fs::write(&path, b"created meanwhile").unwrap();
+
+            // As if the file appeared after the stale-socket check.
+            let err = bind_with_mode(&path, 0o600).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::AddrInUse);
+            assert_eq!(fs::read(&path).unwrap(), b"created meanwhile");
+            fs::remove_file(&path).unwrap();
+
+            let staging = format!(".aegis-appeared-{}.sock.", std::process::id());
+            let leftovers = fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.file_name().to_string_lossy().starts_with(&staging));
+            assert!(!leftovers);
+        }
     }
 }
@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::core::addr::PeerAddr;
+use crate::core::enums::HttpStatus;
+use crate::core::headers::HeaderMap;
+use crate::core::response::Response;
+
+use super::chunked::ChunkedDecoder;
+use super::connection::ByteStream;
+use super::listener::Listener;
+
+// Bytes a duplex pipe buffers in each direction before writes wait.
+const DEFAULT_PIPE_SIZE: usize = 64 * 1024;
+
+// Fake client ports, so each in-memory connection has its own address.
+static NEXT_PORT: AtomicU16 = AtomicU16::new(1);
+
+/// In-process listener for tests: every `MemoryConnector::connect` hands
+/// the server one end of a `tokio::io::duplex` pipe, no sockets involved.
+pub struct MemoryListener {
+    rx: Mutex<mpsc::Receiver<(DuplexStream, PeerAddr)>>,
+}
+
+/// Opens connections to a `MemoryListener`.
+#[derive(Clone)]
+pub struct MemoryConnector {
+    tx: mpsc::Sender<(DuplexStream, PeerAddr)>,
+    pipe_size: usize,
+}
+
+impl MemoryListener {
+    pub fn new() -> (Self, MemoryConnector) {
+        Self::with_pipe_size(DEFAULT_PIPE_SIZE)
+    }
+
+    /// Like `new`, with `pipe_size` bytes buffered in each direction.
+    pub fn with_pipe_size(pipe_size: usize) -> (Self, MemoryConnector) {
+        let (tx, rx) = mpsc::channel(64);
+        let listener = Self { rx: Mutex::new(rx) };
+        (listener, MemoryConnector { tx, pipe_size })
+    }
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)> {
+        match self.rx.lock().await.recv().await {
+            Some((stream, peer)) => Ok((Box::new(MemoryByteStream { stream }), peer)),
+            // Like a socket nobody connects to any more.
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl MemoryConnector {
+    /// Connects from `127.0.0.1` on a fresh fake port.
+    pub async fn connect(&self) -> Result<DuplexStream> {
+        let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+        self.connect_from(PeerAddr::Tcp(([127, 0, 0, 1], port).into()))
+            .await
+    }
+
+    /// Connects with `peer` as the client address the server sees.
+    pub async fn connect_from(&self, peer: PeerAddr) -> Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(self.pipe_size);
+        self.tx
+            .send((server, peer))
+            .await
+            .map_err(|_| Error::new(ErrorKind::ConnectionRefused, "memory listener is gone"))?;
+        Ok(client)
+    }
+}
+
+struct MemoryByteStream {
+    stream: DuplexStream,
+}
+
+impl AsyncRead for MemoryByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryByteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl ByteStream for MemoryByteStream {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await
+    }
+
+    async fn close(&mut self) -> () {
+        let _ = self.stream.shutdown().await;
+    }
+}
+
+/// Minimal HTTP/1.1 client for tests: writes raw request bytes and parses
+/// what comes back into `Response`s. Works over any stream, in-memory or
+/// TCP. Bodies are framed by `Content-Length`, chunked encoding or the end
+/// of the connection; responses to HEAD are not told apart.
+pub struct TestClient<S> {
+    stream: S,
+    // Read but not yet parsed, e.g. the next pipelined response.
+    buf: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TestClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Writes raw bytes, e.g. a request or part of one.
+    pub async fn send(&mut self, raw: &[u8]) -> Result<()> {
+        self.stream.write_all(raw).await?;
+        self.stream.flush().await
+    }
+
+    /// Sends `raw` and reads the response to it.
+    pub async fn request(&mut self, raw: &[u8]) -> Result<Response> {
+        self.send(raw).await?;
+        self.read_response().await
+    }
+
+    /// Reads the next response.
+    pub async fn read_response(&mut self) -> Result<Response> {
+        // 1. Head
+        let (head_len, mut response) = loop {
+            if let Some(parsed) = parse_head(&self.buf)? {
+                break parsed;
+            }
+            self.fill().await?;
+        };
+        self.buf.drain(..head_len);
+
+        // 2. Body
+        let code = response.status.code();
+        let no_body = (100..200).contains(&code) || code == 204 || code == 304;
+        if no_body {
+            return Ok(response);
+        }
+
+        if response.headers.has_token("Transfer-Encoding", "chunked") {
+            let mut decoder = ChunkedDecoder::new(usize::MAX);
+            loop {
+                let used = decoder
+                    .decode(&self.buf, &mut response.body)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                self.buf.drain(..used);
+                if decoder.is_done() {
+                    break;
+                }
+                self.fill().await?;
+            }
+        } else if let Some(length) = response.headers.get_str("Content-Length") {
+            let length: usize = length
+                .trim()
+                .parse()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid Content-Length"))?;
+            while self.buf.len() < length {
+                self.fill().await?;
+            }
+            response.body = self.buf.drain(..length).collect();
+        } else {
+            self.stream.read_to_end(&mut self.buf).await?;
+            response.body = std::mem::take(&mut self.buf);
+        }
+        Ok(response)
+    }
+
+    /// Waits for the server to close the connection. Returns whatever
+    /// arrived in the meantime, empty if nothing did.
+    pub async fn read_to_close(&mut self) -> Result<Vec<u8>> {
+        self.stream.read_to_end(&mut self.buf).await?;
+        Ok(std::mem::take(&mut self.buf))
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk).await? {
+            0 => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed mid-response",
+            )),
+            n => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parses a response head, `None` while it is incomplete.
+fn parse_head(buf: &[u8]) -> Result<Option<(usize, Response)>> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let head_len = match parsed.parse(buf) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+    };
+
+    let status = parsed
+        .code
+        .and_then(HttpStatus::from_code)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid status code"))?;
+    let response = Response {
+        status,
+        headers: HeaderMap::from_httparse(parsed.headers),
+        body: Vec::new(),
+        version: format!("HTTP/1.{}", parsed.version.unwrap_or(1)),
+        stream: None,
+    };
+    Ok(Some((head_len, response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::Event;
+    use crate::core::structs::ServerConfig;
+    use crate::protocols::tcp::server::run_server;
+    use std::sync::Arc;
+
+    /// Echoes the request path back, streamed when it starts with /stream.
+    async fn spawn_server() -> MemoryConnector {
+        let (listener, connector) = MemoryListener::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        let config = Arc::new(ServerConfig::default());
+        tokio::spawn(run_server(Box::new(listener), tx, config));
+
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let Event::RequestStart {
+                    uri, mut resp_tx, ..
+                } = event
+                else {
+                    continue;
+                };
+                if uri.path.starts_with("/stream") {
+                    resp_tx.start(Response::ok()).await.unwrap();
+                    for part in ["a", "b", "c"] {
+                        resp_tx.body(part.as_bytes().to_vec(), true).await.unwrap();
+                    }
+                    resp_tx.body(Vec::new(), false).await.unwrap();
+                } else {
+                    let _ = resp_tx.send(Response::ok().body(uri.path.into_bytes()));
+                }
+            }
+        });
+        connector
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_requests() {
+        let connector = spawn_server().await;
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+
+        let first = client.request(b"GET /one HTTP/1.1\r\n\r\n").await.unwrap();
+        assert_eq!(first.status, HttpStatus::Ok);
+        assert_eq!(first.body, b"/one");
+
+        let second = client.request(b"GET /two HTTP/1.1\r\n\r\n").await.unwrap();
+        assert_eq!(second.body, b"/two");
+        assert_eq!(second.version, "HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_and_chunked_responses() {
+        let connector = spawn_server().await;
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+        client
+            .send(b"GET /stream HTTP/1.1\r\n\r\nGET /after HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let streamed = client.read_response().await.unwrap();
+        assert_eq!(
+            streamed.headers.get_str("Transfer-Encoding"),
+            Some("chunked")
+        );
+        assert_eq!(streamed.body, b"abc");
+
+        let after = client.read_response().await.unwrap();
+        assert_eq!(after.body, b"/after");
+        assert!(client.read_to_close().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_sets_client_addr() {
+        let (listener, connector) = MemoryListener::new();
+        let peer = PeerAddr::Tcp("10.1.2.3:4000".parse().unwrap());
+        let _client = connector.connect_from(peer.clone()).await.unwrap();
+
+        let (_stream, accepted) = listener.accept().await.unwrap();
+        assert_eq!(accepted, peer);
+
+        drop(listener);
+        let err = connector.connect().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn test_parse_head_waits_for_the_whole_head() {
+        assert!(parse_head(b"HTTP/1.1 200 OK\r\nContent-Le")
+            .unwrap()
+            .is_none());
+
+        let raw = b"HTTP/1.0 418 I'm a teapot\r\nX-A: 1\r\n\r\nrest";
+        let (len, response) = parse_head(raw).unwrap().unwrap();
+        assert_eq!(&raw[len..], b"rest");
+        assert_eq!(response.status.code(), 418);
+        assert_eq!(response.version, "HTTP/1.0");
+        assert_eq!(response.headers.get_str("X-A"), Some("1"));
+    }
+}
@@ -1,3 +1,7 @@
+pub(crate) mod admission;
+pub mod chunked;
 pub mod connection;
 pub mod listener;
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
 pub mod server;
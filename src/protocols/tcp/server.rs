@@ -1,189 +1,1742 @@
 use httparse::{Header, Request, Status};
+use std::fmt;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
-use tracing::{debug, error, warn};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::core::events::Event;
-use crate::core::response::Response;
-use crate::core::structs::{RequestMeta, ServerConfig};
+use crate::core::addr::PeerAddr;
+use crate::core::enums::{AcceptErrorKind, HttpStatus, Method, ParsingMode, Timeout};
+use crate::core::events::{Event, LifespanAck};
+use crate::core::handle::ServerHandle;
+use crate::core::headers::HeaderMap;
+use crate::core::response::{Response, ResponseMessage, ResponseSender};
+use crate::core::structs::{ConnectionId, FramingError, RequestId, RequestMeta, ServerConfig};
+use crate::core::uri::{Uri, UriError};
 
+#[cfg(feature = "http2")]
+use crate::protocols::http2;
+
+use crate::protocols::websocket;
+
+use super::admission::Admission;
+use super::chunked::{encode_chunk, ChunkedDecoder, ChunkedError, LAST_CHUNK};
+use super::connection::ByteStream;
 use super::listener::Listener;
 
+// Bounds of the pause after an accept error that retrying will not fix
+// right away, such as running out of file descriptors.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(5);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+// Interim response to `Expect: 100-continue`.
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+#[cfg(feature = "http2")]
+const SWITCHING_TO_H2C: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/// Parsed request line and headers of a single request.
+struct RequestHead {
+    method: Method,
+    uri: Uri,
+    version: u8,
+    headers: HeaderMap,
+    meta: RequestMeta,
+    // Number of bytes the head occupied in the buffer.
+    length: usize,
+}
+
+#[derive(Debug)]
+enum HeadError {
+    EmptyMethod,
+    EmptyPath,
+    Target(UriError),
+    Parse(httparse::Error),
+    // A line ending in LF without CR, refused in strict mode.
+    BareLineFeed,
+    Framing(FramingError),
+}
+
+impl HeadError {
+    fn status(&self) -> HttpStatus {
+        match self {
+            Self::Parse(httparse::Error::TooManyHeaders) => HttpStatus::RequestHeaderFieldsTooLarge,
+            _ => HttpStatus::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for HeadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyMethod => write!(f, "empty method"),
+            Self::EmptyPath => write!(f, "empty path"),
+            Self::Target(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::BareLineFeed => write!(f, "line ending without CR"),
+            Self::Framing(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Error response the server answers with before closing the connection.
+struct Rejection {
+    status: HttpStatus,
+    reason: String,
+    // Set when the rejection is due to an expired timeout.
+    timeout: Option<Timeout>,
+    // Added to the error response.
+    headers: Vec<(&'static str, &'static str)>,
+}
+
+impl Rejection {
+    fn new(status: HttpStatus, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: reason.into(),
+            timeout: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn timeout(timeout: Timeout) -> Self {
+        Self {
+            status: timeout.status(),
+            reason: format!("{} expired", timeout),
+            timeout: Some(timeout),
+            headers: Vec::new(),
+        }
+    }
+
+    fn from_body_error(e: ChunkedError) -> Self {
+        let status = match e {
+            ChunkedError::PayloadTooLarge { .. } => HttpStatus::ContentTooLarge,
+            _ => HttpStatus::BadRequest,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+/// Framing of the request body being read.
+enum BodyDecoder {
+    // Bytes still expected from a `Content-Length` body.
+    Length(usize),
+    Chunked(ChunkedDecoder),
+}
+
+impl BodyDecoder {
+    fn for_request(meta: &RequestMeta, limit: usize, mode: ParsingMode) -> Self {
+        if meta.is_chunked {
+            Self::Chunked(ChunkedDecoder::new(limit).mode(mode))
+        } else {
+            Self::Length(meta.content_length.unwrap_or(0))
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self {
+            Self::Length(remaining) => *remaining == 0,
+            Self::Chunked(decoder) => decoder.is_done(),
+        }
+    }
+
+    /// Moves body bytes from `input` into `out`, returning how many input
+    /// bytes belonged to the body.
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize, ChunkedError> {
+        match self {
+            Self::Length(remaining) => {
+                let take = input.len().min(*remaining);
+                out.extend_from_slice(&input[..take]);
+                *remaining -= take;
+                Ok(take)
+            }
+            Self::Chunked(decoder) => decoder.decode(input, out),
+        }
+    }
+}
+
+/// Progress of the response to the request in flight.
+enum ResponseState {
+    // Waiting for `ResponseMessage::Start`.
+    Pending,
+    Streaming { chunked: bool, send_body: bool },
+}
+
+/// What the connection does after a response message was handled.
+enum Written {
+    More,
+    Complete,
+    Close,
+}
+
+/// State of the request currently being served on a connection.
+struct InFlight {
+    id: RequestId,
+    version: u8,
+    is_head: bool,
+    keep_alive: bool,
+    // The client holds the body back until `100 Continue` is sent.
+    expects_continue: bool,
+    // When the head was parsed, for the response timeout.
+    started: Instant,
+    // Last time body bytes arrived, for the body read timeout.
+    last_read: Instant,
+    body: BodyDecoder,
+    response: ResponseState,
+    // `None` once the response has been written.
+    response_rx: Option<mpsc::Receiver<ResponseMessage>>,
+}
+
+impl InFlight {
+    fn is_finished(&self) -> bool {
+        self.response_rx.is_none() && self.body.is_done()
+    }
+
+    /// The timeout that applies while this request is in flight. None once
+    /// the response is streaming, which may legitimately take long.
+    fn deadline(&self, config: &ServerConfig) -> Option<(Instant, Timeout)> {
+        if self.expects_continue {
+            // The application has yet to ask for the body.
+            Some((self.started + config.response_timeout, Timeout::Response))
+        } else if !self.body.is_done() {
+            Some((self.last_read + config.body_read_timeout, Timeout::BodyRead))
+        } else if self.response_rx.is_some() && matches!(self.response, ResponseState::Pending) {
+            Some((self.started + config.response_timeout, Timeout::Response))
+        } else {
+            None
+        }
+    }
+}
+
+/// Accepts connections and reports their requests on `tx` until the
+/// listener fails for good. Other accept errors are counted in
+/// `ServerHandle::stats` and retried, with a growing pause while the
+/// process is out of resources.
+///
+/// `Event::Startup` is sent first and must be answered (or dropped) before
+/// connections are accepted; a failed startup is returned as an error.
 pub async fn run_server(
     listener: Box<dyn Listener>,
     tx: mpsc::Sender<Event>,
     config: Arc<ServerConfig>,
 ) -> tokio::io::Result<()> {
-    loop {
-        let (mut stream, client_addr) = listener.accept().await?;
-        let tx = tx.clone();
-        let config = Arc::clone(&config);
-        let connection_span = tracing::info_span!("http_conn", client = %client_addr);
-        let _enter = connection_span.enter();
+    run_server_with_handle(listener, tx, config, ServerHandle::new()).await
+}
 
-        debug!("Accepted connection");
+/// Like `run_server`, but returns once `handle` is shut down and the open
+/// connections have finished their current request. Connections still busy
+/// after `ServerConfig::shutdown_timeout` are aborted. `Event::Shutdown` is
+/// sent last.
+pub async fn run_server_with_handle(
+    listener: Box<dyn Listener>,
+    tx: mpsc::Sender<Event>,
+    config: Arc<ServerConfig>,
+    handle: ServerHandle,
+) -> tokio::io::Result<()> {
+    // Lifespan startup: the application may veto before anything is accepted.
+    let (ack, answer) = LifespanAck::channel();
+    if tx.send(Event::Startup { ack }).await.is_ok() {
+        if let Ok(Err(reason)) = answer.await {
+            error!("Application startup failed: {}", reason);
+            return Err(tokio::io::Error::other(format!(
+                "application startup failed: {}",
+                reason
+            )));
+        }
+    }
+    info!("Accepting connections");
 
-        tokio::spawn(async move {
-            let mut header_buffer = Vec::new();
-            let mut headers_done = false;
-            let mut body_bytes_received = 0usize;
-            let mut expected_length: Option<usize> = None;
-            let mut temp_buf = vec![0u8; config.read_buffer_size];
-            let mut response_rx: Option<tokio::sync::oneshot::Receiver<Response>> = None;
-
-            loop {
-                tokio::select! {
-                    // 1. Reading the tcp-socket.
-                    read_result = stream.read(&mut temp_buf) => {
-                        let n = match read_result {
-                            Ok(0) => {
-                                // Client disconnected
-                                debug!("Client Disconnected");
-                                let _ = tx.send(Event::Disconnect { client_addr }).await;
-                                break;
-                            }
-                            Ok(n) => n,
-                            Err(e) => {
-                                // Error while reading.
-                                error!("Error while listening: {:?}", e);
-                                let _ = tx.send(Event::Disconnect { client_addr }).await;
-                                break;
-                            }
-                        };
-                        if !headers_done {
+    let admission = Arc::new(Admission::new(&config, handle.clone()));
+    let mut connections = JoinSet::new();
+    let mut backoff: Option<Duration> = None;
 
-                            // 2. Reading headers.
-                            header_buffer.extend_from_slice(&temp_buf[..n]);
+    'accept: loop {
+        // Room under `max_connections`, when the limit policy waits for it.
+        let reserved = tokio::select! {
+            reserved = admission.reserve() => reserved,
+            _ = handle.wait() => break,
+        };
 
-                            // CONDITION
-                            // If header_buffer is bigger than MAX_PAYLOAD_SIZE.
-                            if header_buffer.len() > config.max_payload_size {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, client_addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        let kind = AcceptErrorKind::of(&e);
+                        let count = handle.stats().record_accept_error(kind);
+                        match kind {
+                            AcceptErrorKind::Fatal => {
+                                error!(count, "Listener failed: {}", e);
+                                // Open connections outlive a failed listener.
+                                connections.detach_all();
+                                return Err(e);
+                            }
+                            AcceptErrorKind::Connection => {
+                                debug!(class = %kind, count, "Accept failed: {}", e);
+                            }
+                            AcceptErrorKind::ResourceExhausted | AcceptErrorKind::Other => {
+                                let delay = backoff
+                                    .map_or(ACCEPT_BACKOFF_MIN, |d| (d * 2).min(ACCEPT_BACKOFF_MAX));
+                                backoff = Some(delay);
                                 warn!(
-                                    received = header_buffer.len(),
-                                    limit = config.max_payload_size,
-                                    "Header is too big."
+                                    class = %kind,
+                                    count,
+                                    delay_ms = delay.as_millis() as u64,
+                                    "Accept failed, backing off: {}",
+                                    e
                                 );
-                                break;
-                            }
-
-                            let mut headers = [Header {
-                                name: "",
-                                value: &[],
-                            }; 64];
-                            let mut req = Request::new(&mut headers);
-
-                            match req.parse(&header_buffer) {
-                                Ok(Status::Complete(amt)) => {
-                                    headers_done = true;
-
-                                    let method = match req.method {
-                                        Some(m) => m.to_string(),
-                                        None => {
-                                            // Raise a 400.
-                                            warn!("Empty method");
-                                            break;
-                                        }
-                                    };
-                                    let path = match req.path {
-                                        Some(p) => p.to_string(),
-                                        None => {
-                                            // Raise a 400.
-                                            warn!("Empty path");
-                                            break;
-                                        }
-                                    };
-                                    let version = req.version.unwrap_or(1);
-
-                                    let meta = RequestMeta::from_headers(req.headers);
-                                    expected_length = meta.content_length;
-
-                                    // CONDITION
-                                    // IF Content-Length is more than MAX_PAYLOAD_SIZE.
-                                    if let Some(len) = expected_length {
-                                        if len > config.max_payload_size {
-                                            warn!(len, limit = config.max_payload_size);
-                                            break;
-                                        }
-                                    }
-
-                                    let rest = header_buffer[amt..].to_vec();
-                                    body_bytes_received = rest.len();
-
-                                    // CONDITION
-                                    // If rest bytes len is bigger than MAX_PAYLOAD_SIZE.
-                                    if body_bytes_received > config.max_payload_size {
-                                        warn!(recieved = body_bytes_received);
-                                        break;
-                                    }
-                                    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
-                                    response_rx = Some(resp_rx);
-                                    let _ = tx
-                                        .send(Event::RequestStart {
-                                            method,
-                                            path,
-                                            version,
-                                            rest,
-                                            meta,
-                                            resp_tx
-                                        })
-                                        .await;
-
-                                    // Request could be stopped
+                                tokio::select! {
+                                    _ = sleep(delay) => {}
+                                    _ = handle.wait() => break 'accept,
                                 }
-                                Ok(Status::Partial) => continue,
-                                Err(_) => break,
-                            }
-                        } else {
-                            // 3. Reading the body.
-                            body_bytes_received += n;
-
-                            // CONDITION
-                            // If received bytes len is more than MAX_PAYLOAD_SIZE.
-                            if body_bytes_received > config.max_payload_size {
-                                warn!(recieved = body_bytes_received);
-                                break;
                             }
+                        }
+                        continue;
+                    }
+                };
+                backoff = None;
+                handle.stats().record_accepted();
+                let connection_id = ConnectionId::next();
+                let tx = tx.clone();
+                let config = Arc::clone(&config);
+                let handle = handle.clone();
+                let admission = Arc::clone(&admission);
+                let connection_span =
+                    tracing::info_span!("http_conn", conn = %connection_id, client = %client_addr);
 
-                            let more_body = expected_length
-                                .map(|len| body_bytes_received < len)
-                                .unwrap_or(false);
+                connections.spawn(
+                    async move {
+                        let mut stream = stream;
+                        let admitted = tokio::select! {
+                            admitted = admission.admit(client_addr.ip(), reserved) => admitted,
+                            _ = handle.wait() => return,
+                        };
+                        let Some(_admitted) = admitted else {
+                            admission.refuse(&mut stream).await;
+                            return;
+                        };
+                        handle_connection(stream, client_addr, connection_id, tx, config, handle).await;
+                    }
+                    .instrument(connection_span),
+                );
+            }
+            // Reap finished connections so the set does not grow.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = handle.wait() => break,
+        }
+    }
 
-                            let event = Event::RequestBody {
-                                body: temp_buf[..n].to_vec(),
-                                more_body,
-                            };
-                            let _ = tx.send(event).await;
+    // 1. Stop accepting.
+    drop(listener);
+    info!(
+        connections = connections.len(),
+        "Shutting down, draining connections"
+    );
 
-                            if !more_body {
-                                let _ = tx.send(Event::Disconnect { client_addr }).await;
-                                // Request could be stopped
-                            }
+    // 2. Wait for the connections to finish their current request.
+    let drained = tokio::time::timeout(config.shutdown_timeout, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            connections = connections.len(),
+            "Shutdown timeout expired, aborting remaining connections"
+        );
+        connections.shutdown().await;
+    }
+
+    // 3. Tell the application and wait for its cleanup.
+    let (ack, answer) = LifespanAck::channel();
+    if tx.send(Event::Shutdown { ack }).await.is_ok() {
+        if let Ok(Err(reason)) = answer.await {
+            error!("Application shutdown failed: {}", reason);
+        }
+    }
+    info!("Server stopped");
+    Ok(())
+}
+
+async fn handle_connection(
+    mut stream: Box<dyn ByteStream>,
+    client_addr: PeerAddr,
+    connection_id: ConnectionId,
+    tx: mpsc::Sender<Event>,
+    config: Arc<ServerConfig>,
+    handle: ServerHandle,
+) {
+    debug!("Accepted connection");
+
+    #[cfg(feature = "http2")]
+    if stream.alpn_protocol() == Some(b"h2") {
+        let conn = http2::Conn {
+            connection_id,
+            client_addr,
+            tx,
+            config,
+        };
+        http2::serve_connection(http2::Rewind::new(Vec::new(), stream), None, conn, handle).await;
+        return;
+    }
+
+    // Bytes read from the socket that do not belong to the current body:
+    // the next request head, possibly pipelined behind the current one.
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut temp_buf = vec![0u8; config.read_buffer_size];
+    let mut current: Option<InFlight> = None;
+    let mut rejection: Option<Rejection> = None;
+    // Whether a request has completed, i.e. the connection is kept alive.
+    let mut served = false;
+    let mut idle_since = Instant::now();
+    // Arrival of the first byte of the pending request head.
+    let mut head_started: Option<Instant> = None;
+    // Set once shutdown starts: no further requests are served.
+    let mut draining = false;
+
+    loop {
+        if draining && current.is_none() {
+            debug!("Closing connection for shutdown");
+            break;
+        }
+
+        // 0. HTTP/2 with prior knowledge: the connection opens with the
+        //    preface instead of a request.
+        #[cfg(feature = "http2")]
+        let awaiting_preface = !served && current.is_none() && http2::is_preface_start(&buffer);
+        #[cfg(not(feature = "http2"))]
+        let awaiting_preface = false;
+        #[cfg(feature = "http2")]
+        if awaiting_preface && buffer.len() >= http2::PREFACE.len() {
+            debug!("Switching to HTTP/2 (prior knowledge)");
+            let conn = http2::Conn {
+                connection_id,
+                client_addr,
+                tx,
+                config,
+            };
+            http2::serve_connection(http2::Rewind::new(buffer, stream), None, conn, handle).await;
+            return;
+        }
+
+        // 1. Start the next request once its head is buffered.
+        if current.is_none() && !buffer.is_empty() && !awaiting_preface {
+            head_started.get_or_insert_with(Instant::now);
+            match parse_head(&buffer, config.parsing_mode) {
+                Ok(Some(head)) => {
+                    // CONDITION
+                    // IF Content-Length is more than MAX_PAYLOAD_SIZE.
+                    if let Some(len) = head.meta.content_length {
+                        if !head.meta.is_chunked && len > config.max_payload_size {
+                            rejection = Some(Rejection::new(
+                                HttpStatus::ContentTooLarge,
+                                format!(
+                                    "Content-Length {} exceeds limit of {} bytes",
+                                    len, config.max_payload_size
+                                ),
+                            ));
+                            break;
                         }
-                    },
-                    res = async {
-                        match response_rx.as_mut() {
-                            Some(rx) => rx.await,
-                            None => std::future::pending().await,
+                    }
+
+                    // CONDITION
+                    // IF the client expects something other than
+                    // 100-continue. HTTP/1.0 clients may not use Expect.
+                    if head.version >= 1 && head.meta.expect_unsupported {
+                        rejection = Some(Rejection::new(
+                            HttpStatus::ExpectationFailed,
+                            "Unsupported Expect header",
+                        ));
+                        break;
+                    }
+
+                    // CONDITION
+                    // IF the client asks to switch to HTTP/2 (h2c); the
+                    // request is then answered on stream 1.
+                    #[cfg(feature = "http2")]
+                    if let Some(frame) = http2::upgrade_frame(
+                        head.method.as_str(),
+                        &head.uri,
+                        head.version,
+                        &head.headers,
+                        &head.meta,
+                    ) {
+                        debug!("Switching to HTTP/2 (h2c upgrade)");
+                        buffer.drain(..head.length);
+                        if let Err(e) = stream.write_all(SWITCHING_TO_H2C).await {
+                            debug!("Failed to send 101 response: {:?}", e);
+                            break;
                         }
-                    } => {
-                        match res {
-                            Ok(response) => {
-                                let data = response.build();
-                                if let Err(e) = stream.write_all(&data).await {
-                                    error!("Failed to send response: {:?}", e);
-                                }
-                                let _ = stream.flush().await;
-                                response_rx = None;
+                        let _ = stream.flush().await;
+                        let conn = http2::Conn {
+                            connection_id,
+                            client_addr,
+                            tx,
+                            config,
+                        };
+                        let io = http2::Rewind::new(buffer, stream);
+                        http2::serve_connection(io, Some(frame), conn, handle).await;
+                        return;
+                    }
+
+                    // CONDITION
+                    // IF the client asks for a WebSocket; the connection is
+                    // then the WebSocket's until it closes.
+                    match websocket::check(&head.method, head.version, &head.headers) {
+                        Some(Ok(handshake)) => {
+                            buffer.drain(..head.length);
+                            let upgrade = websocket::Upgrade {
+                                request_id: RequestId::next(),
+                                uri: head.uri,
+                                headers: head.headers,
+                                handshake,
+                            };
+                            let session = websocket::Session {
+                                connection_id,
+                                client_addr: &client_addr,
+                                tx: &tx,
+                                config: &config,
+                                handle: &handle,
+                            };
+                            websocket::serve(
+                                &mut stream,
+                                std::mem::take(&mut buffer),
+                                upgrade,
+                                session,
+                            )
+                            .await;
+                            break;
+                        }
+                        Some(Err(e)) => {
+                            let mut error = Rejection::new(e.status, e.reason);
+                            if e.status == HttpStatus::UpgradeRequired {
+                                error = error.header("Sec-WebSocket-Version", "13");
                             }
-                            Err(_) => {
-                                warn!("Logic dropped response_tx without responding");
+                            rejection = Some(error);
+                            break;
+                        }
+                        None => {}
+                    }
+
+                    let id = RequestId::next();
+                    debug!(request = %id, chunked = head.meta.is_chunked, "Request started");
+                    head_started = None;
+
+                    buffer.drain(..head.length);
+                    let mut body = BodyDecoder::for_request(
+                        &head.meta,
+                        config.max_payload_size,
+                        config.parsing_mode,
+                    );
+                    let mut rest = Vec::new();
+                    match body.decode(&buffer, &mut rest) {
+                        Ok(consumed) => {
+                            buffer.drain(..consumed);
+                        }
+                        Err(e) => {
+                            rejection = Some(Rejection::from_body_error(e));
+                            break;
+                        }
+                    }
+
+                    let (resp_tx, resp_rx) = ResponseSender::channel();
+                    let more_body = !body.is_done();
+                    // A client that already started on the body needs no
+                    // go-ahead.
+                    let expects_continue = head.version >= 1
+                        && head.meta.expect_continue
+                        && more_body
+                        && rest.is_empty();
+                    current = Some(InFlight {
+                        id,
+                        version: head.version,
+                        is_head: head.method == Method::Head,
+                        keep_alive: head.meta.wants_keep_alive(head.version),
+                        expects_continue,
+                        started: Instant::now(),
+                        last_read: Instant::now(),
+                        body,
+                        response: ResponseState::Pending,
+                        response_rx: Some(resp_rx),
+                    });
+
+                    let _ = tx
+                        .send(Event::RequestStart {
+                            connection_id,
+                            request_id: id,
+                            client_addr: client_addr.clone(),
+                            method: head.method,
+                            uri: head.uri,
+                            version: head.version,
+                            headers: head.headers,
+                            rest,
+                            more_body,
+                            meta: head.meta,
+                            resp_tx,
+                        })
+                        .await;
+                    continue;
+                }
+                Ok(None) => {
+                    // CONDITION
+                    // If the pending head is bigger than MAX_PAYLOAD_SIZE.
+                    if buffer.len() > config.max_payload_size {
+                        rejection = Some(Rejection::new(
+                            HttpStatus::RequestHeaderFieldsTooLarge,
+                            format!(
+                                "Header is too big: {} bytes, limit is {}",
+                                buffer.len(),
+                                config.max_payload_size
+                            ),
+                        ));
+                        break;
+                    }
+                }
+                Err(e) => {
+                    rejection = Some(Rejection::new(
+                        e.status(),
+                        format!("Malformed request head: {}", e),
+                    ));
+                    break;
+                }
+            }
+        }
+
+        // 5. Pick the timeout for the current state.
+        let deadline = match (&current, head_started) {
+            (Some(request), _) => request.deadline(&config).map(|(at, t)| (at, Some(t))),
+            (None, Some(started)) => Some((
+                started + config.header_read_timeout,
+                Some(Timeout::HeaderRead),
+            )),
+            // Idle connections have no request to answer and are closed
+            // silently.
+            (None, None) if served => Some((idle_since + config.keep_alive_timeout, None)),
+            (None, None) => Some((idle_since + config.header_read_timeout, None)),
+        };
+
+        tokio::select! {
+            // 2. Reading the tcp-socket.
+            read_result = stream.read(&mut temp_buf) => {
+                let n = match read_result {
+                    Ok(0) => {
+                        // Client disconnected
+                        debug!("Client Disconnected");
+                        break;
+                    }
+                    Ok(n) => n,
+                    Err(e) => {
+                        // Error while reading.
+                        error!("Error while listening: {:?}", e);
+                        break;
+                    }
+                };
+
+                match current.as_mut() {
+                    Some(request) if !request.body.is_done() => {
+                        // 3. Reading the body.
+                        request.last_read = Instant::now();
+                        // Clients stop waiting for `100 Continue` after a while.
+                        request.expects_continue = false;
+                        let mut body = Vec::new();
+                        let consumed = match request.body.decode(&temp_buf[..n], &mut body) {
+                            Ok(consumed) => consumed,
+                            Err(e) => {
+                                rejection = Some(Rejection::from_body_error(e));
                                 break;
                             }
+                        };
+                        let more_body = !request.body.is_done();
+
+                        // Chunk framing alone decodes to nothing worth reporting.
+                        if !body.is_empty() || !more_body {
+                            let event = Event::RequestBody {
+                                connection_id,
+                                request_id: request.id,
+                                body,
+                                more_body,
+                            };
+                            let _ = tx.send(event).await;
                         }
+
+                        // Anything past the body is the next pipelined request.
+                        buffer.extend_from_slice(&temp_buf[consumed..n]);
+                    }
+                    _ => buffer.extend_from_slice(&temp_buf[..n]),
+                }
+
+                // CONDITION
+                // If buffered bytes are bigger than MAX_PAYLOAD_SIZE.
+                if buffer.len() > config.max_payload_size {
+                    rejection = Some(Rejection::new(
+                        HttpStatus::RequestHeaderFieldsTooLarge,
+                        format!(
+                            "Buffered {} bytes ahead of the next request, limit is {}",
+                            buffer.len(),
+                            config.max_payload_size
+                        ),
+                    ));
+                    break;
+                }
+
+                if current.as_ref().is_some_and(InFlight::is_finished) {
+                    current = None;
+                    served = true;
+                    idle_since = Instant::now();
+                }
+            },
+            // 4. Writing the response.
+            message = async {
+                match current.as_mut().and_then(|r| r.response_rx.as_mut()) {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let Some(request) = current.as_mut() else {
+                    continue;
+                };
+                let Some(message) = message else {
+                    if matches!(request.response, ResponseState::Pending) {
+                        rejection = Some(Rejection::new(
+                            HttpStatus::InternalServerError,
+                            "Logic dropped resp_tx without responding",
+                        ));
+                    } else {
+                        warn!(request = %request.id, "Logic dropped resp_tx without finishing the response");
                     }
+                    break;
                 };
+
+                match write_response_message(&mut stream, request, message).await {
+                    Written::More => {}
+                    Written::Complete => {
+                        request.response_rx = None;
+                        if request.is_finished() {
+                            current = None;
+                            served = true;
+                            idle_since = Instant::now();
+                        }
+                    }
+                    Written::Close => {
+                        debug!(request = %request.id, "Closing connection after response");
+                        stream.close().await;
+                        break;
+                    }
+                }
+            }
+            // 6. Finishing the current request, then closing, on shutdown.
+            _ = handle.wait(), if !draining => {
+                draining = true;
+                if let Some(request) = current.as_mut() {
+                    request.keep_alive = false;
+                }
+            }
+            // 7. Giving up on a slow client or application.
+            _ = async {
+                match deadline {
+                    Some((at, _)) => sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match deadline.and_then(|(_, timeout)| timeout) {
+                    Some(timeout) => rejection = Some(Rejection::timeout(timeout)),
+                    None => debug!("Closing idle connection"),
+                }
+                break;
+            }
+        };
+    }
+
+    let request_id = current.as_ref().map(|r| r.id);
+    if let Some(Rejection {
+        status,
+        reason,
+        timeout,
+        headers,
+    }) = rejection
+    {
+        warn!(status = status.code(), "Rejecting request: {}", reason);
+
+        // Once a response has started there is no way to replace it.
+        let started = current
+            .as_ref()
+            .is_some_and(|r| matches!(r.response, ResponseState::Streaming { .. }));
+        if !started {
+            let mut response = Response::error(status).header("Connection", "close");
+            for (name, value) in headers {
+                response = response.header(name, value);
+            }
+            let data = response.build();
+            if let Err(e) = stream.write_all(&data).await {
+                debug!("Failed to send error response: {:?}", e);
+            }
+            let _ = stream.flush().await;
+        }
+        stream.close().await;
+
+        let event = match timeout {
+            Some(timeout) => Event::RequestTimeout {
+                connection_id,
+                request_id,
+                client_addr: client_addr.clone(),
+                timeout,
+            },
+            None => Event::RequestRejected {
+                connection_id,
+                request_id,
+                client_addr: client_addr.clone(),
+                status,
+                reason,
+            },
+        };
+        let _ = tx.send(event).await;
+    }
+
+    let _ = tx
+        .send(Event::Disconnect {
+            connection_id,
+            request_id,
+            client_addr,
+        })
+        .await;
+}
+
+/// Writes one response message for `request` to the socket.
+async fn write_response_message(
+    stream: &mut Box<dyn ByteStream>,
+    request: &mut InFlight,
+    message: ResponseMessage,
+) -> Written {
+    let mut data = Vec::new();
+    let finished = match (message, &request.response) {
+        (ResponseMessage::Continue, _) => {
+            if !request.expects_continue {
+                return Written::More;
+            }
+            request.expects_continue = false;
+            // The body read timeout starts with the go-ahead.
+            request.last_read = Instant::now();
+            data.extend_from_slice(CONTINUE);
+            false
+        }
+        (ResponseMessage::Start(mut head), ResponseState::Pending) => {
+            // Without a `100 Continue` the body never comes.
+            request.expects_continue = false;
+            let send_body = !request.is_head && head.status.allows_body();
+            let has_length = head.has_header("Content-Length");
+            // Without a length the body is chunked on HTTP/1.1 and
+            // delimited by closing the connection on HTTP/1.0.
+            let chunked = send_body && !has_length && request.version >= 1;
+            if send_body && !has_length && !chunked {
+                request.keep_alive = false;
+            }
+
+            // An unread body cannot be skipped safely, so the connection
+            // only survives when the whole request has been consumed.
+            if !request.body.is_done() || head.closes_connection() {
+                request.keep_alive = false;
+            }
+
+            if chunked {
+                head = head.header("Transfer-Encoding", "chunked");
+            }
+            if !request.keep_alive {
+                head = head.header("Connection", "close");
+            } else if request.version == 0 {
+                head = head.header("Connection", "keep-alive");
+            }
+
+            data.extend_from_slice(&head.build_head());
+            request.response = ResponseState::Streaming { chunked, send_body };
+            false
+        }
+        (
+            ResponseMessage::Body {
+                data: chunk,
+                more_body,
+            },
+            ResponseState::Streaming { chunked, send_body },
+        ) => {
+            if *send_body {
+                if *chunked {
+                    encode_chunk(&chunk, &mut data);
+                    if !more_body {
+                        data.extend_from_slice(LAST_CHUNK);
+                    }
+                } else {
+                    data = chunk;
+                }
+            }
+            !more_body
+        }
+        (ResponseMessage::Start(_), ResponseState::Streaming { .. }) => {
+            warn!(request = %request.id, "Response started twice");
+            return Written::Close;
+        }
+        (ResponseMessage::Body { .. }, ResponseState::Pending) => {
+            warn!(request = %request.id, "Response body sent before start");
+            return Written::Close;
+        }
+    };
+
+    if !data.is_empty() {
+        if let Err(e) = stream.write_all(&data).await {
+            error!("Failed to send response: {:?}", e);
+            return Written::Close;
+        }
+        let _ = stream.flush().await;
+    }
+
+    match (finished, request.keep_alive) {
+        (false, _) => Written::More,
+        (true, true) => Written::Complete,
+        (true, false) => Written::Close,
+    }
+}
+
+/// Parses a request head from the start of `buf`.
+/// Returns `Ok(None)` while the head is still incomplete.
+fn parse_head(buf: &[u8], mode: ParsingMode) -> Result<Option<RequestHead>, HeadError> {
+    let mut headers = [Header {
+        name: "",
+        value: &[],
+    }; 64];
+    let mut req = Request::new(&mut headers);
+
+    match req.parse(buf) {
+        Ok(Status::Complete(length)) => {
+            // CONDITION
+            // IF a line ends in a bare LF, which proxies may not split on.
+            if mode == ParsingMode::Strict
+                && buf[..length]
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &b)| b == b'\n' && (i == 0 || buf[i - 1] != b'\r'))
+            {
+                return Err(HeadError::BareLineFeed);
+            }
+            let method = Method::parse(req.method.ok_or(HeadError::EmptyMethod)?);
+            let path = req.path.ok_or(HeadError::EmptyPath)?;
+            let uri = Uri::parse(path).map_err(HeadError::Target)?;
+            let version = req.version.unwrap_or(1);
+            let meta = RequestMeta::from_headers(req.headers, mode).map_err(HeadError::Framing)?;
+            let headers = HeaderMap::from_httparse(req.headers);
+
+            Ok(Some(RequestHead {
+                method,
+                uri,
+                version,
+                headers,
+                meta,
+                length,
+            }))
+        }
+        Ok(Status::Partial) => Ok(None),
+        Err(e) => Err(HeadError::Parse(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::LimitPolicy;
+    use crate::protocols::tcp::listener::TcpByteListener;
+    use std::net::SocketAddr;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    async fn spawn_server() -> (SocketAddr, mpsc::Receiver<Event>) {
+        spawn_server_with(ServerConfig {
+            max_payload_size: 1024,
+            read_buffer_size: 64,
+            ..ServerConfig::default()
+        })
+        .await
+    }
+
+    async fn spawn_server_with(config: ServerConfig) -> (SocketAddr, mpsc::Receiver<Event>) {
+        let (addr, rx, _, _) = spawn_server_with_handle(config).await;
+        (addr, rx)
+    }
+
+    /// Starts a server and acknowledges its startup.
+    async fn spawn_server_with_handle(
+        mut config: ServerConfig,
+    ) -> (
+        SocketAddr,
+        mpsc::Receiver<Event>,
+        ServerHandle,
+        tokio::task::JoinHandle<tokio::io::Result<()>>,
+    ) {
+        let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        config.addr = addr.into();
+        let (tx, mut rx) = mpsc::channel(16);
+        let handle = ServerHandle::new();
+        let server = tokio::spawn(run_server_with_handle(
+            Box::new(listener),
+            tx,
+            Arc::new(config),
+            handle.clone(),
+        ));
+
+        let Some(Event::Startup { ack }) = rx.recv().await else {
+            panic!("expected Startup");
+        };
+        ack.ok();
+        (addr, rx, handle, server)
+    }
+
+    fn short_timeouts() -> ServerConfig {
+        let timeout = std::time::Duration::from_millis(100);
+        ServerConfig {
+            header_read_timeout: timeout,
+            body_read_timeout: timeout,
+            keep_alive_timeout: timeout,
+            response_timeout: timeout,
+            ..ServerConfig::default()
+        }
+    }
+
+    async fn read_until_closed(client: &mut TcpStream) -> String {
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_is_chunked() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart { mut resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        resp_tx.start(Response::ok()).await.unwrap();
+        resp_tx.body(b"hello ".to_vec(), true).await.unwrap();
+        resp_tx.body(b"world".to_vec(), false).await.unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(raw.ends_with("\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_with_length_is_not_chunked() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart { mut resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        resp_tx
+            .start(Response::ok().header("Content-Length", "4"))
+            .await
+            .unwrap();
+        resp_tx.body(b"ab".to_vec(), true).await.unwrap();
+        resp_tx.body(b"cd".to_vec(), false).await.unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(!raw.contains("Transfer-Encoding"));
+        assert!(raw.ends_with("\r\n\r\nabcd"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_content_length_is_rejected_with_413() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 4096\r\n\r\n")
+            .await
+            .unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(raw.contains("Connection: close\r\n"));
+
+        let Some(Event::RequestRejected { status, .. }) = rx.recv().await else {
+            panic!("expected RequestRejected");
+        };
+        assert_eq!(status, HttpStatus::ContentTooLarge);
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_waits_for_the_application() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\
+                  Connection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart {
+            resp_tx,
+            meta,
+            more_body,
+            ..
+        }) = rx.recv().await
+        else {
+            panic!("expected RequestStart");
+        };
+        assert!(meta.expect_continue);
+        assert!(more_body);
+        resp_tx.send_continue().await.unwrap();
+
+        let mut interim = [0u8; CONTINUE.len()];
+        client.read_exact(&mut interim).await.unwrap();
+        assert_eq!(interim, CONTINUE);
+        client.write_all(b"abc").await.unwrap();
+
+        let Some(Event::RequestBody { body, .. }) = rx.recv().await else {
+            panic!("expected RequestBody");
+        };
+        assert_eq!(body, b"abc");
+        resp_tx.send(Response::ok()).unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_answered_early() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PUT / HTTP/1.1\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\r\n")
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        resp_tx
+            .send(Response::error(HttpStatus::ExpectationFailed))
+            .unwrap();
+
+        // No `100 Continue`, and the unread body rules out keep-alive.
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+        assert!(raw.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_checks_size_first() {
+        let (addr, _rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PUT / HTTP/1.1\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n")
+            .await
+            .unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_expectation_is_rejected_with_417() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nExpect: teapot\r\n\r\n")
+            .await
+            .unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+
+        let Some(Event::RequestRejected { status, .. }) = rx.recv().await else {
+            panic!("expected RequestRejected");
+        };
+        assert_eq!(status, HttpStatus::ExpectationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_head_is_rejected_with_431() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut raw = b"GET / HTTP/1.1\r\n".to_vec();
+        raw.extend_from_slice(format!("X-Big: {}\r\n", "a".repeat(2048)).as_bytes());
+        client.write_all(&raw).await.unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::RequestRejected {
+                status: HttpStatus::RequestHeaderFieldsTooLarge,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_head_is_rejected_with_400() {
+        let (addr, _rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/9.9\r\n\r\n").await.unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_framing_is_rejected_with_400() {
+        let (addr, mut rx) = spawn_server().await;
+        let smuggling: [&[u8]; 11] = [
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n",
+            b"GET / HTTP/1.1\nHost: a\n\n",
+            b"GET / HTTP/1.1\r\nX-A: 1\r\n folded\r\n\r\n",
+            b"GET / HTTP/1.1\r\nX-A: a\x00b\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;\nabc\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5 junk\r\nhello\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;a\x01\r\nabc\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-A: 1\n\r\n",
+        ];
+
+        for raw in smuggling {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(raw).await.unwrap();
+
+            let out = read_until_closed(&mut client).await;
+            assert!(
+                out.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{:?} answered with {:?}",
+                String::from_utf8_lossy(raw),
+                out
+            );
+            // Nothing reaches the application.
+            assert!(matches!(
+                rx.recv().await,
+                Some(Event::RequestRejected {
+                    status: HttpStatus::BadRequest,
+                    ..
+                })
+            ));
+            assert!(matches!(rx.recv().await, Some(Event::Disconnect { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lenient_mode_prefers_chunked_and_closes() {
+        let (addr, mut rx) = spawn_server_with(ServerConfig {
+            parsing_mode: ParsingMode::Lenient,
+            ..ServerConfig::default()
+        })
+        .await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"POST / HTTP/1.1\nContent-Length: 100\nTransfer-Encoding: chunked\n\n\
+                  3\r\nabc\r\n0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart {
+            resp_tx,
+            rest,
+            meta,
+            ..
+        }) = rx.recv().await
+        else {
+            panic!("expected RequestStart");
+        };
+        assert!(meta.is_chunked);
+        assert_eq!(rest, b"abc");
+        resp_tx.send(Response::ok()).unwrap();
+
+        let out = read_until_closed(&mut client).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_sender_answers_500() {
+        let (addr, mut rx) = spawn_server().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        drop(resp_tx);
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_head_times_out_with_408() {
+        let (addr, mut rx) = spawn_server_with(short_timeouts()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHo").await.unwrap();
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::RequestTimeout {
+                request_id: None,
+                timeout: Timeout::HeaderRead,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_body_times_out_with_408() {
+        let (addr, mut rx) = spawn_server_with(short_timeouts()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab")
+            .await
+            .unwrap();
+
+        let Some(Event::RequestStart {
+            request_id,
+            resp_tx: _resp_tx,
+            ..
+        }) = rx.recv().await
+        else {
+            panic!("expected RequestStart");
+        };
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        let Some(Event::RequestTimeout {
+            request_id: Some(id),
+            timeout,
+            ..
+        }) = rx.recv().await
+        else {
+            panic!("expected RequestTimeout");
+        };
+        assert_eq!(id, request_id);
+        assert_eq!(timeout, Timeout::BodyRead);
+    }
+
+    #[tokio::test]
+    async fn test_slow_application_times_out_with_504() {
+        let (addr, mut rx) = spawn_server_with(short_timeouts()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        // Holding the sender without responding.
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::RequestTimeout {
+                timeout: Timeout::Response,
+                ..
+            })
+        ));
+        assert!(resp_tx.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_idle_keep_alive_connection_is_closed() {
+        let (addr, mut rx) = spawn_server_with(short_timeouts()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        resp_tx.send(Response::ok()).unwrap();
+
+        // The response arrives, then the connection is closed without a 408.
+        let raw = read_until_closed(&mut client).await;
+        assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!raw.contains("408"));
+        assert!(matches!(rx.recv().await, Some(Event::Disconnect { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_request() {
+        let (addr, mut rx, handle, server) =
+            spawn_server_with_handle(ServerConfig::default()).await;
+        // Accepted before `busy`, whose request proves it was accepted.
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        let mut busy = TcpStream::connect(addr).await.unwrap();
+        busy.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+
+        handle.shutdown();
+        // Idle connections are closed right away.
+        assert_eq!(read_until_closed(&mut idle).await, "");
+
+        resp_tx.send(Response::ok().body(b"done".to_vec())).unwrap();
+        let raw = read_until_closed(&mut busy).await;
+        assert!(raw.contains("Connection: close\r\n"));
+        assert!(raw.ends_with("\r\n\r\ndone"));
+
+        loop {
+            match rx.recv().await {
+                Some(Event::Shutdown { ack }) => break ack.ok(),
+                Some(_) => {}
+                None => panic!("expected Shutdown"),
+            }
+        }
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_vetoed_startup_fails_without_accepting() {
+        let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        let server = tokio::spawn(run_server(
+            Box::new(listener),
+            tx,
+            Arc::new(ServerConfig::default()),
+        ));
+
+        let Some(Event::Startup { ack }) = rx.recv().await else {
+            panic!("expected Startup");
+        };
+        ack.fail("database unreachable");
+
+        let err = server.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("database unreachable"));
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_acknowledgement() {
+        let (_addr, mut rx, handle, server) =
+            spawn_server_with_handle(ServerConfig::default()).await;
+
+        handle.shutdown();
+        let Some(Event::Shutdown { ack }) = rx.recv().await else {
+            panic!("expected Shutdown");
+        };
+        tokio::task::yield_now().await;
+        assert!(!server.is_finished());
+
+        ack.ok();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_gives_up_after_timeout() {
+        let (addr, mut rx, handle, server) = spawn_server_with_handle(ServerConfig {
+            shutdown_timeout: std::time::Duration::from_millis(100),
+            ..ServerConfig::default()
+        })
+        .await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let Some(Event::RequestStart {
+            resp_tx: _resp_tx, ..
+        }) = rx.recv().await
+        else {
+            panic!("expected RequestStart");
+        };
+
+        handle.shutdown();
+        // Aborted connections send no further events.
+        assert!(matches!(rx.recv().await, Some(Event::Shutdown { .. })));
+        server.await.unwrap().unwrap();
+    }
+
+    fn limited(max: Option<usize>, per_ip: Option<usize>, policy: LimitPolicy) -> ServerConfig {
+        ServerConfig {
+            max_connections: max,
+            max_connections_per_ip: per_ip,
+            connection_limit_policy: policy,
+            ..ServerConfig::default()
+        }
+    }
+
+    /// Connects and waits until the server reports the request.
+    async fn hold_request(
+        addr: SocketAddr,
+        rx: &mut mpsc::Receiver<Event>,
+    ) -> (TcpStream, ResponseSender) {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let Some(Event::RequestStart { resp_tx, .. }) = rx.recv().await else {
+            panic!("expected RequestStart");
+        };
+        (client, resp_tx)
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_rejects_with_503() {
+        let (addr, mut rx, handle, _server) =
+            spawn_server_with_handle(limited(Some(1), None, LimitPolicy::Reject)).await;
+        let (_held, _resp_tx) = hold_request(addr, &mut rx).await;
+
+        let mut refused = TcpStream::connect(addr).await.unwrap();
+        let raw = read_until_closed(&mut refused).await;
+        assert!(raw.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert_eq!(handle.stats().active_connections(), 1);
+        assert_eq!(handle.stats().refused_connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_limit_closes() {
+        let (addr, mut rx, handle, _server) =
+            spawn_server_with_handle(limited(None, Some(1), LimitPolicy::Close)).await;
+        let (_held, _resp_tx) = hold_request(addr, &mut rx).await;
+        assert_eq!(handle.stats().connections_from(addr.ip()), 1);
+
+        let mut refused = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_until_closed(&mut refused).await, "");
+        assert_eq!(handle.stats().refused_connections(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serves_unix_socket_without_per_ip_limit() {
+        use crate::protocols::tcp::listener::UnixByteListener;
+        use tokio::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("aegis-server-{}.sock", std::process::id()));
+        let listener = UnixByteListener::bind(&path, None).await.unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        let config = limited(None, Some(1), LimitPolicy::Close);
+        tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config)));
+        let Some(Event::Startup { ack }) = rx.recv().await else {
+            panic!("expected Startup");
+        };
+        ack.ok();
+
+        // Unix peers have no IP, so the per-IP cap of 1 does not apply.
+        let mut first = UnixStream::connect(&path).await.unwrap();
+        let mut second = UnixStream::connect(&path).await.unwrap();
+        for client in [&mut first, &mut second] {
+            client
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+        }
+        let mut senders = Vec::new();
+        while senders.len() < 2 {
+            if let Some(Event::RequestStart {
+                client_addr,
+                resp_tx,
+                ..
+            }) = rx.recv().await
+            {
+                assert_eq!(client_addr, PeerAddr::Unix(None));
+                senders.push(resp_tx);
+            }
+        }
+        for resp_tx in senders {
+            resp_tx
+                .send(Response::ok().body(b"over unix".to_vec()))
+                .unwrap();
+        }
+
+        for client in [&mut first, &mut second] {
+            let mut out = Vec::new();
+            client.read_to_end(&mut out).await.unwrap();
+            let raw = String::from_utf8(out).unwrap();
+            assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(raw.ends_with("over unix"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_waits_for_a_slot() {
+        let (addr, mut rx, handle, _server) =
+            spawn_server_with_handle(limited(Some(1), None, LimitPolicy::Wait)).await;
+        let (mut held, resp_tx) = hold_request(addr, &mut rx).await;
+
+        let mut waiting = TcpStream::connect(addr).await.unwrap();
+        waiting
+            .write_all(b"GET /next HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let early = tokio::time::timeout(std::time::Duration::from_millis(100), rx.recv()).await;
+        assert!(early.is_err(), "second connection served over the limit");
+
+        // Finishing the first connection lets the second one in.
+        resp_tx
+            .send(Response::ok().header("Connection", "close"))
+            .unwrap();
+        read_until_closed(&mut held).await;
+        let next = loop {
+            match rx.recv().await {
+                Some(Event::RequestStart { uri, resp_tx, .. }) => break (uri, resp_tx),
+                Some(_) => {}
+                None => panic!("expected RequestStart"),
+            }
+        };
+        assert_eq!(next.0.path, "/next");
+        next.1.send(Response::ok()).unwrap();
+        assert!(read_until_closed(&mut waiting)
+            .await
+            .starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(handle.stats().refused_connections(), 0);
+    }
+
+    /// Fails `accept` with the queued errors before accepting for real.
+    struct FlakyListener {
+        errors: std::sync::Mutex<Vec<std::io::Error>>,
+        inner: TcpByteListener,
+    }
+
+    #[async_trait::async_trait]
+    impl Listener for FlakyListener {
+        async fn accept(&self) -> tokio::io::Result<(Box<dyn ByteStream>, PeerAddr)> {
+            let error = self.errors.lock().unwrap().pop();
+            match error {
+                Some(e) => Err(e),
+                None => self.inner.accept().await,
+            }
+        }
+    }
+
+    async fn spawn_flaky_server(
+        errors: Vec<std::io::Error>,
+    ) -> (
+        SocketAddr,
+        ServerHandle,
+        tokio::task::JoinHandle<tokio::io::Result<()>>,
+    ) {
+        let inner = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = inner.local_addr().unwrap();
+        let listener = FlakyListener {
+            errors: std::sync::Mutex::new(errors.into_iter().rev().collect()),
+            inner,
+        };
+        let (tx, mut rx) = mpsc::channel(16);
+        let handle = ServerHandle::new();
+        let server = tokio::spawn(run_server_with_handle(
+            Box::new(listener),
+            tx,
+            Arc::new(ServerConfig::default()),
+            handle.clone(),
+        ));
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Event::RequestStart { resp_tx, .. } = event {
+                    let _ = resp_tx.send(Response::ok());
+                }
             }
         });
+        (addr, handle, server)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_accept_loop_survives_transient_errors() {
+        use std::io::Error;
+
+        let (addr, handle, server) = spawn_flaky_server(vec![
+            Error::from_raw_os_error(libc::EMFILE),
+            Error::from_raw_os_error(libc::EMFILE),
+            Error::from_raw_os_error(libc::ECONNABORTED),
+            Error::other("unexpected"),
+        ])
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_until_closed(&mut client)
+            .await
+            .starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let stats = handle.stats();
+        assert_eq!(stats.accepted(), 1);
+        assert_eq!(stats.accept_errors(AcceptErrorKind::ResourceExhausted), 2);
+        assert_eq!(stats.accept_errors(AcceptErrorKind::Connection), 1);
+        assert_eq!(stats.accept_errors(AcceptErrorKind::Other), 1);
+        assert_eq!(stats.accept_errors(AcceptErrorKind::Fatal), 0);
+
+        handle.shutdown();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_accept_loop_stops_on_fatal_error() {
+        let (_addr, handle, server) =
+            spawn_flaky_server(vec![std::io::Error::from(std::io::ErrorKind::InvalidInput)]).await;
+
+        assert!(server.await.unwrap().is_err());
+        assert_eq!(handle.stats().accept_errors(AcceptErrorKind::Fatal), 1);
+    }
+
+    #[test]
+    fn test_parse_head_complete() {
+        let raw = b"POST /items HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET";
+        let head = parse_head(raw, ParsingMode::Strict).unwrap().unwrap();
+
+        assert_eq!(head.method, Method::Post);
+        assert_eq!(head.uri.path, "/items");
+        assert_eq!(head.version, 1);
+        assert_eq!(head.meta.content_length, Some(3));
+        assert_eq!(head.headers.get_str("content-length"), Some("3"));
+        assert_eq!(&raw[head.length..], b"abcGET");
+    }
+
+    #[test]
+    fn test_parse_head_exposes_uri_and_headers() {
+        let raw = b"GET /search/caf%C3%A9?q=a+b HTTP/1.1\r\nHost: example.com\r\nCookie: a=1\r\nCookie: b=2\r\n\r\n";
+        let head = parse_head(raw, ParsingMode::Strict).unwrap().unwrap();
+
+        assert_eq!(head.method, Method::Get);
+        assert_eq!(head.uri.segments, vec!["search", "café"]);
+        assert_eq!(head.uri.query_param("q"), Some("a b"));
+        assert_eq!(head.headers.get_str("host"), Some("example.com"));
+        assert_eq!(head.headers.get_all("cookie").count(), 2);
+    }
+
+    #[test]
+    fn test_parse_head_partial() {
+        assert!(
+            parse_head(b"GET / HTTP/1.1\r\nHost: x\r\n", ParsingMode::Strict)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_parse_head_garbage() {
+        assert!(matches!(
+            parse_head(b"\x01\x02 / HTTP/1.1\r\n\r\n", ParsingMode::Strict),
+            Err(HeadError::Parse(_))
+        ));
     }
 }
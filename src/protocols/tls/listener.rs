@@ -0,0 +1,424 @@
+use async_trait::async_trait;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tokio_rustls::rustls;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn, Instrument};
+
+use crate::core::addr::PeerAddr;
+use crate::core::structs::ServerConfig;
+use crate::protocols::tcp::connection::ByteStream;
+use crate::protocols::tcp::listener::Listener;
+
+use super::resolver::CertResolver;
+
+// Finished handshakes waiting for the server to pick them up.
+const ACCEPTED_BACKLOG: usize = 64;
+
+type Accepted = Result<(Box<dyn ByteStream>, PeerAddr)>;
+
+/// Terminates TLS on the connections of an inner listener.
+///
+/// Handshakes run in their own tasks, so a slow client never holds up the
+/// accept loop; only finished handshakes are handed out by `accept`.
+/// Errors of the inner listener are passed through as they are.
+pub struct TlsListener {
+    rx: Mutex<mpsc::Receiver<Accepted>>,
+    certs: Arc<CertResolver>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TlsListener {
+    /// Wraps `inner` with the certificates, ALPN protocols and timeouts
+    /// of `config`. Must be called within a Tokio runtime.
+    pub fn new(inner: impl Listener + 'static, config: &ServerConfig) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let certs = Arc::new(CertResolver::from_config(config, Arc::clone(&provider))?);
+
+        let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(std::io::Error::other)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&certs) as Arc<dyn rustls::server::ResolvesServerCert>);
+        tls.alpn_protocols = config
+            .tls_alpn
+            .iter()
+            .map(|p| p.as_bytes().to_vec())
+            .collect();
+
+        let (tx, rx) = mpsc::channel(ACCEPTED_BACKLOG);
+        let mut tasks = vec![tokio::spawn(accept_loop(
+            Box::new(inner),
+            TlsAcceptor::from(Arc::new(tls)),
+            config.tls_handshake_timeout,
+            tx,
+        ))];
+        if let Some(period) = config.tls_reload_interval {
+            tasks.push(tokio::spawn(watch_certs(Arc::clone(&certs), period)));
+        }
+
+        Ok(Self {
+            rx: Mutex::new(rx),
+            certs,
+            tasks,
+        })
+    }
+
+    /// The certificates in use, e.g. to `reload` them on SIGHUP.
+    pub fn certs(&self) -> Arc<CertResolver> {
+        Arc::clone(&self.certs)
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl Listener for TlsListener {
+    async fn accept(&self) -> Result<(Box<dyn ByteStream>, PeerAddr)> {
+        match self.rx.lock().await.recv().await {
+            Some(accepted) => accepted,
+            // The accept task is gone only while the listener is dropped.
+            None => std::future::pending().await,
+        }
+    }
+}
+
+async fn accept_loop(
+    inner: Box<dyn Listener>,
+    acceptor: TlsAcceptor,
+    handshake_timeout: std::time::Duration,
+    tx: mpsc::Sender<Accepted>,
+) {
+    loop {
+        let (stream, peer) = match inner.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // The server classifies the error and backs off; a full
+                // channel holds this loop back meanwhile.
+                if tx.send(Err(e)).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let tx = tx.clone();
+        let span = tracing::debug_span!("tls_handshake", client = %peer);
+        tokio::spawn(
+            async move {
+                match timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let stream: Box<dyn ByteStream> = Box::new(TlsByteStream { stream });
+                        let _ = tx.send(Ok((stream, peer))).await;
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                    Err(_) => debug!("TLS handshake timed out"),
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+async fn watch_certs(certs: Arc<CertResolver>, period: std::time::Duration) {
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes at once.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match certs.reload_if_changed() {
+            Ok(true) => info!("TLS certificates reloaded"),
+            Ok(false) => {}
+            Err(e) => warn!("Keeping current TLS certificates, reload failed: {}", e),
+        }
+    }
+}
+
+pub struct TlsByteStream {
+    stream: TlsStream<Box<dyn ByteStream>>,
+}
+
+impl AsyncRead for TlsByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsByteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl ByteStream for TlsByteStream {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await
+    }
+
+    async fn close(&mut self) -> () {
+        // Sends close_notify before closing the inner stream.
+        let _ = self.stream.shutdown().await;
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.stream.get_ref().1.alpn_protocol()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::HttpStatus;
+    use crate::core::events::Event;
+    use crate::core::response::Response;
+    use crate::core::structs::CertPaths;
+    use crate::protocols::tcp::memory::{MemoryConnector, MemoryListener, TestClient};
+    use crate::protocols::tcp::server::run_server;
+    use rustls_pki_types::{CertificateDer, ServerName};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio_rustls::client::TlsStream as ClientTlsStream;
+    use tokio_rustls::TlsConnector;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aegis-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a self-signed certificate for `host` to `paths`.
+    fn write_cert(paths: &CertPaths, host: &str) -> CertificateDer<'static> {
+        let generated = rcgen::generate_simple_self_signed(vec![host.to_string()]).unwrap();
+        std::fs::write(&paths.cert, generated.cert.pem()).unwrap();
+        std::fs::write(&paths.key, generated.key_pair.serialize_pem()).unwrap();
+        generated.cert.der().clone()
+    }
+
+    fn cert_paths(dir: &std::path::Path, name: &str) -> CertPaths {
+        CertPaths {
+            cert: dir.join(format!("{}.pem", name)),
+            key: dir.join(format!("{}.key", name)),
+        }
+    }
+
+    async fn connect(
+        connector: &MemoryConnector,
+        trusted: &CertificateDer<'static>,
+        host: &str,
+    ) -> Result<ClientTlsStream<DuplexStream>> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(trusted.clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let name = ServerName::try_from(host.to_string()).unwrap();
+        let stream = connector.connect().await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_serves_http_over_tls() {
+        let dir = test_dir("serve");
+        let paths = cert_paths(&dir, "server");
+        let trusted = write_cert(&paths, "localhost");
+        let config = ServerConfig {
+            tls_cert: Some(paths),
+            ..ServerConfig::default()
+        };
+
+        let (inner, connector) = MemoryListener::new();
+        let listener = TlsListener::new(inner, &config).unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config)));
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Event::RequestStart { resp_tx, .. } = event {
+                    let _ = resp_tx.send(Response::ok().body(b"secure".to_vec()));
+                }
+            }
+        });
+
+        let stream = connect(&connector, &trusted, "localhost").await.unwrap();
+        // Only http/1.1 is offered by default.
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        let mut client = TestClient::new(stream);
+        let response = client.request(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.body, b"secure");
+    }
+
+    #[tokio::test]
+    async fn test_sni_selects_certificate() {
+        let dir = test_dir("sni");
+        let (default, exact, wildcard) = (
+            cert_paths(&dir, "default"),
+            cert_paths(&dir, "exact"),
+            cert_paths(&dir, "wildcard"),
+        );
+        let default_der = write_cert(&default, "localhost");
+        let exact_der = write_cert(&exact, "a.test");
+        let wildcard_der = write_cert(&wildcard, "*.b.test");
+        let config = ServerConfig {
+            tls_cert: Some(default),
+            tls_sni_certs: vec![
+                ("a.test".to_string(), exact),
+                ("*.b.test".to_string(), wildcard),
+            ],
+            tls_alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            ..ServerConfig::default()
+        };
+        let (inner, connector) = MemoryListener::new();
+        let listener = TlsListener::new(inner, &config).unwrap();
+
+        // Each host gets the certificate the client trusts for it.
+        let exact = connect(&connector, &exact_der, "a.test").await.unwrap();
+        assert_eq!(exact.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        assert!(connect(&connector, &wildcard_der, "api.b.test")
+            .await
+            .is_ok());
+        assert!(connect(&connector, &default_der, "localhost").await.is_ok());
+        // Wildcards cover a single label only.
+        assert!(connect(&connector, &wildcard_der, "v1.api.b.test")
+            .await
+            .is_err());
+
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(stream.alpn_protocol(), Some(&b"h2"[..]));
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn test_alpn_h2_serves_http2() {
+        let dir = test_dir("h2");
+        let paths = cert_paths(&dir, "server");
+        let trusted = write_cert(&paths, "localhost");
+        let config = ServerConfig {
+            tls_cert: Some(paths),
+            tls_alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            ..ServerConfig::default()
+        };
+
+        let (inner, connector) = MemoryListener::new();
+        let listener = TlsListener::new(inner, &config).unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config)));
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Event::RequestStart {
+                    version, resp_tx, ..
+                } = event
+                {
+                    let body = format!("HTTP version {}", version).into_bytes();
+                    let _ = resp_tx.send(Response::ok().body(body));
+                }
+            }
+        });
+
+        let stream = connect(&connector, &trusted, "localhost").await.unwrap();
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let request = http::Request::get("https://localhost/").body(()).unwrap();
+        let (response, _) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, true)
+            .unwrap();
+        let mut body = response.await.unwrap().into_body();
+        let data = body.data().await.unwrap().unwrap();
+        assert_eq!(&data[..], b"HTTP version 2");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout_does_not_block_accept() {
+        let dir = test_dir("timeout");
+        let paths = cert_paths(&dir, "server");
+        let trusted = write_cert(&paths, "localhost");
+        let config = ServerConfig {
+            tls_cert: Some(paths),
+            tls_handshake_timeout: Duration::from_millis(100),
+            ..ServerConfig::default()
+        };
+        let (inner, connector) = MemoryListener::new();
+        let listener = TlsListener::new(inner, &config).unwrap();
+
+        let mut silent = connector.connect().await.unwrap();
+        let _client = connect(&connector, &trusted, "localhost").await.unwrap();
+        let (_stream, _) = listener.accept().await.unwrap();
+
+        // The silent client is dropped once its handshake times out.
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(2), silent.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reload_swaps_certificates() {
+        let dir = test_dir("reload");
+        let paths = cert_paths(&dir, "server");
+        let first = write_cert(&paths, "localhost");
+        let config = ServerConfig {
+            tls_cert: Some(paths.clone()),
+            ..ServerConfig::default()
+        };
+        let (inner, connector) = MemoryListener::new();
+        let listener = TlsListener::new(inner, &config).unwrap();
+        assert!(connect(&connector, &first, "localhost").await.is_ok());
+        assert!(!listener.certs().reload_if_changed().unwrap());
+
+        let second = write_cert(&paths, "localhost");
+        listener.certs().reload().unwrap();
+        assert!(connect(&connector, &second, "localhost").await.is_ok());
+        assert!(connect(&connector, &first, "localhost").await.is_err());
+
+        // A broken key keeps the loaded certificate in use.
+        std::fs::write(&paths.key, "not a key").unwrap();
+        assert!(listener.certs().reload().is_err());
+        assert!(connect(&connector, &second, "localhost").await.is_ok());
+    }
+}
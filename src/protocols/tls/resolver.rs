@@ -0,0 +1,147 @@
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+
+use crate::core::structs::{CertPaths, ServerConfig};
+
+/// Picks the certificate for a handshake by SNI host name, falling back to
+/// the default one. Certificates can be reloaded from disk at any time;
+/// handshakes already under way keep the ones they started with.
+#[derive(Debug)]
+pub struct CertResolver {
+    default: Option<CertPaths>,
+    by_name: Vec<(String, CertPaths)>,
+    provider: Arc<CryptoProvider>,
+    loaded: RwLock<Arc<Loaded>>,
+}
+
+#[derive(Debug, Default)]
+struct Loaded {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    // Modification times of every file, in `CertResolver::paths` order.
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl CertResolver {
+    /// Loads the certificates named by `config`.
+    pub fn from_config(config: &ServerConfig, provider: Arc<CryptoProvider>) -> Result<Self> {
+        if !config.tls_enabled() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "no TLS certificate configured",
+            ));
+        }
+
+        let resolver = Self {
+            default: config.tls_cert.clone(),
+            by_name: config
+                .tls_sni_certs
+                .iter()
+                .map(|(host, paths)| (host.to_ascii_lowercase(), paths.clone()))
+                .collect(),
+            provider,
+            loaded: RwLock::default(),
+        };
+        resolver.reload()?;
+        Ok(resolver)
+    }
+
+    /// Reads every certificate again. On error the current ones stay in use.
+    pub fn reload(&self) -> Result<()> {
+        let loaded = self.load()?;
+        *self.loaded.write().unwrap() = Arc::new(loaded);
+        Ok(())
+    }
+
+    /// Reloads if any certificate or key file changed since the last load.
+    /// Returns whether it did.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let current = Arc::clone(&self.loaded.read().unwrap());
+        if self.modified() == current.modified {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.default
+            .iter()
+            .chain(self.by_name.iter().map(|(_, paths)| paths))
+            .flat_map(|paths| [&paths.cert, &paths.key])
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn load(&self) -> Result<Loaded> {
+        // Taken first, so a file changing mid-load is picked up next time.
+        let modified = self.modified();
+
+        let default = match &self.default {
+            Some(paths) => Some(self.load_one(paths)?),
+            None => None,
+        };
+        let mut by_name = HashMap::new();
+        for (host, paths) in &self.by_name {
+            by_name.insert(host.clone(), self.load_one(paths)?);
+        }
+
+        Ok(Loaded {
+            default,
+            by_name,
+            modified,
+        })
+    }
+
+    fn load_one(&self, paths: &CertPaths) -> Result<Arc<CertifiedKey>> {
+        let invalid = |path: &PathBuf, e: &dyn std::fmt::Display| {
+            Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        };
+
+        let chain = CertificateDer::pem_file_iter(&paths.cert)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&paths.cert, &e))?;
+        if chain.is_empty() {
+            return Err(invalid(&paths.cert, &"no certificates found"));
+        }
+        let key = PrivateKeyDer::from_pem_file(&paths.key).map_err(|e| invalid(&paths.key, &e))?;
+
+        let certified = CertifiedKey::from_der(chain, key, &self.provider)
+            .map_err(|e| invalid(&paths.key, &e))?;
+        Ok(Arc::new(certified))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let loaded = Arc::clone(&self.loaded.read().unwrap());
+
+        if let Some(name) = client_hello.server_name() {
+            let name = name.to_ascii_lowercase();
+            if let Some(certified) = loaded.by_name.get(&name) {
+                return Some(Arc::clone(certified));
+            }
+            // `*.example.com` covers `api.example.com` but not
+            // `v1.api.example.com`.
+            if let Some((_, parent)) = name.split_once('.') {
+                if let Some(certified) = loaded.by_name.get(&format!("*.{}", parent)) {
+                    return Some(Arc::clone(certified));
+                }
+            }
+        }
+        loaded.default.clone()
+    }
+}
@@ -0,0 +1,247 @@
+use crate::core::websocket::close_code;
+
+pub(crate) const OP_CONTINUATION: u8 = 0x0;
+pub(crate) const OP_TEXT: u8 = 0x1;
+pub(crate) const OP_BINARY: u8 = 0x2;
+pub(crate) const OP_CLOSE: u8 = 0x8;
+pub(crate) const OP_PING: u8 = 0x9;
+pub(crate) const OP_PONG: u8 = 0xA;
+
+// Control frames carry at most this many payload bytes.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// One frame sent by the client, already unmasked.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: u8,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Why the connection has to be closed, as sent in the close frame.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct FrameError {
+    pub(crate) code: u16,
+    pub(crate) reason: &'static str,
+}
+
+impl FrameError {
+    pub(crate) fn protocol(reason: &'static str) -> Self {
+        Self {
+            code: close_code::PROTOCOL_ERROR,
+            reason,
+        }
+    }
+}
+
+/// Parses a client frame from the start of `buf`. Returns the frame and the
+/// number of bytes it took, or `Ok(None)` while it is still incomplete.
+pub(crate) fn decode(buf: &[u8], max_payload: usize) -> Result<Option<(Frame, usize)>, FrameError> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0f;
+    // CONDITION
+    // IF reserved bits are set: no extension was negotiated that uses them.
+    if buf[0] & 0x70 != 0 {
+        return Err(FrameError::protocol("reserved bits set"));
+    }
+    if !matches!(
+        opcode,
+        OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG
+    ) {
+        return Err(FrameError::protocol("unknown opcode"));
+    }
+    if buf[1] & 0x80 == 0 {
+        return Err(FrameError::protocol("client frames must be masked"));
+    }
+
+    let (length, mut pos) = match buf[1] & 0x7f {
+        126 if buf.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() < 10 => return Ok(None),
+        127 => {
+            let length = u64::from_be_bytes(buf[2..10].try_into().unwrap());
+            if length >> 63 != 0 {
+                return Err(FrameError::protocol("invalid payload length"));
+            }
+            (length, 10)
+        }
+        length => (length as u64, 2),
+    };
+
+    if opcode >= OP_CLOSE && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(FrameError::protocol("invalid control frame"));
+    }
+    // CONDITION
+    // IF the frame alone is bigger than a whole message may be.
+    if length > max_payload as u64 {
+        return Err(FrameError {
+            code: close_code::MESSAGE_TOO_BIG,
+            reason: "message too big",
+        });
+    }
+    let length = length as usize;
+
+    if buf.len() < pos + 4 + length {
+        return Ok(None);
+    }
+    let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+    pos += 4;
+    let payload = buf[pos..pos + length]
+        .iter()
+        .zip(mask.iter().cycle())
+        .map(|(byte, key)| byte ^ key)
+        .collect();
+
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        pos + length,
+    )))
+}
+
+/// Appends an unmasked, unfragmented server frame to `out`.
+pub(crate) fn encode(opcode: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => out.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+}
+
+/// Payload of a close frame. The reason is cut to fit a control frame.
+pub(crate) fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    [&code.to_be_bytes()[..], &reason.as_bytes()[..end]].concat()
+}
+
+/// Reads the code and reason of a client's close frame. A frame without a
+/// code is reported as `close_code::NO_STATUS`.
+pub(crate) fn parse_close(payload: &[u8]) -> Result<(u16, String), FrameError> {
+    match payload {
+        [] => Ok((close_code::NO_STATUS, String::new())),
+        [_] => Err(FrameError::protocol("truncated close code")),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            // Codes a peer may send (RFC 6455, section 7.4).
+            if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+                return Err(FrameError::protocol("invalid close code"));
+            }
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| FrameError {
+                code: close_code::INVALID_PAYLOAD,
+                reason: "close reason is not UTF-8",
+            })?;
+            Ok((code, reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A masked client frame.
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut out = Vec::new();
+        encode(0, payload, &mut out);
+        out[0] = first;
+        out[1] |= 0x80;
+        let header_len = out.len() - payload.len();
+        let masked: Vec<u8> = payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, k)| b ^ k)
+            .collect();
+        out.truncate(header_len);
+        out.extend_from_slice(&mask);
+        out.extend_from_slice(&masked);
+        out
+    }
+
+    #[test]
+    fn test_decode_masked_text() {
+        // "Hello" from RFC 6455, section 5.7.
+        let raw = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (frame, used) = decode(&raw, 1024).unwrap().unwrap();
+        assert_eq!(used, raw.len());
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OP_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+
+        assert_eq!(decode(&raw[..7], 1024), Ok(None));
+    }
+
+    #[test]
+    fn test_decode_extended_lengths() {
+        let medium = vec![7u8; 300];
+        let raw = client_frame(0x82, &medium);
+        let (frame, used) = decode(&raw, 1024).unwrap().unwrap();
+        assert_eq!((frame.payload, used), (medium, raw.len()));
+
+        let large = vec![1u8; 70_000];
+        let raw = client_frame(0x82, &large);
+        assert_eq!(decode(&raw, 100_000).unwrap().unwrap().0.payload, large);
+        assert_eq!(
+            decode(&raw, 1024).unwrap_err().code,
+            close_code::MESSAGE_TOO_BIG
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_protocol_errors() {
+        // Unmasked.
+        assert!(decode(&[0x81, 0x00], 1024).is_err());
+        // Reserved bit.
+        assert!(decode(&client_frame(0xc1, b"x"), 1024).is_err());
+        // Unknown opcode.
+        assert!(decode(&client_frame(0x83, b"x"), 1024).is_err());
+        // Fragmented ping.
+        assert!(decode(&client_frame(0x09, b"x"), 1024).is_err());
+        // Oversized control frame.
+        assert!(decode(&client_frame(0x89, &[0; 126]), 1024).is_err());
+    }
+
+    #[test]
+    fn test_encode_lengths() {
+        let mut out = Vec::new();
+        encode(OP_TEXT, b"Hello", &mut out);
+        assert_eq!(out, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+
+        out.clear();
+        encode(OP_BINARY, &[0; 256], &mut out);
+        assert_eq!(&out[..4], &[0x82, 126, 0x01, 0x00]);
+
+        out.clear();
+        encode(OP_BINARY, &[0; 65_536], &mut out);
+        assert_eq!(&out[..10], &[0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_close_payload_round_trip() {
+        let payload = close_payload(1000, "bye");
+        assert_eq!(parse_close(&payload), Ok((1000, "bye".to_string())));
+        assert_eq!(parse_close(&[]), Ok((close_code::NO_STATUS, String::new())));
+        assert!(parse_close(&[0x03]).is_err());
+        assert!(parse_close(&close_payload(1005, "")).is_err());
+        assert_eq!(close_payload(1000, &"é".repeat(100)).len(), 124);
+    }
+}
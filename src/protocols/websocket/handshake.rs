@@ -0,0 +1,249 @@
+use crate::core::enums::{HttpStatus, Method};
+use crate::core::headers::HeaderMap;
+
+// Appended to the client's key before hashing (RFC 6455, section 1.3).
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// A valid opening handshake.
+#[derive(Debug)]
+pub(crate) struct Handshake {
+    // Value of the `Sec-WebSocket-Accept` response header.
+    pub(crate) accept: String,
+    // Subprotocols the client offered, most preferred first.
+    pub(crate) protocols: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct HandshakeError {
+    pub(crate) status: HttpStatus,
+    pub(crate) reason: &'static str,
+}
+
+impl HandshakeError {
+    fn bad_request(reason: &'static str) -> Self {
+        Self {
+            status: HttpStatus::BadRequest,
+            reason,
+        }
+    }
+}
+
+/// Checks a request for `Upgrade: websocket`. Returns `None` for ordinary
+/// requests.
+///
+/// Extensions such as permessage-deflate are not negotiated, so the
+/// client's `Sec-WebSocket-Extensions` offer is ignored.
+pub(crate) fn check(
+    method: &Method,
+    version: u8,
+    headers: &HeaderMap,
+) -> Option<Result<Handshake, HandshakeError>> {
+    if !headers.has_token("Upgrade", "websocket") {
+        return None;
+    }
+    Some(validate(method, version, headers))
+}
+
+fn validate(
+    method: &Method,
+    version: u8,
+    headers: &HeaderMap,
+) -> Result<Handshake, HandshakeError> {
+    if *method != Method::Get || version != 1 {
+        return Err(HandshakeError::bad_request(
+            "WebSocket handshake must be a GET over HTTP/1.1",
+        ));
+    }
+    if !headers.has_token("Connection", "upgrade") {
+        return Err(HandshakeError::bad_request(
+            "WebSocket handshake without Connection: Upgrade",
+        ));
+    }
+    // CONDITION
+    // IF the client speaks another protocol version, tell it ours.
+    if headers.get("Sec-WebSocket-Version").map(<[u8]>::trim_ascii) != Some(b"13") {
+        return Err(HandshakeError {
+            status: HttpStatus::UpgradeRequired,
+            reason: "unsupported Sec-WebSocket-Version",
+        });
+    }
+    let key = headers
+        .get("Sec-WebSocket-Key")
+        .map(<[u8]>::trim_ascii)
+        .filter(|key| is_valid_key(key))
+        .ok_or(HandshakeError::bad_request(
+            "missing or invalid Sec-WebSocket-Key",
+        ))?;
+
+    let protocols = headers
+        .get_all("Sec-WebSocket-Protocol")
+        .flat_map(|value| value.split(|&b| b == b','))
+        .filter_map(|token| std::str::from_utf8(token.trim_ascii()).ok())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Ok(Handshake {
+        accept: accept_key(key),
+        protocols,
+    })
+}
+
+/// The key is 16 random bytes in base64, always 24 characters long.
+fn is_valid_key(key: &[u8]) -> bool {
+    key.len() == 24 && key.ends_with(b"==") && key[..22].iter().all(|b| BASE64_ALPHABET.contains(b))
+}
+
+/// `Sec-WebSocket-Accept` for a client's `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &[u8]) -> String {
+    base64(&sha1(&[key, ACCEPT_GUID].concat()))
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// SHA-1 (RFC 3174). Only used for the handshake, where it is not a
+/// security measure.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, state) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(name, value).unwrap();
+        }
+        map
+    }
+
+    const UPGRADE: [(&str, &str); 4] = [
+        ("Upgrade", "websocket"),
+        ("Connection", "keep-alive, Upgrade"),
+        ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ("Sec-WebSocket-Version", "13"),
+    ];
+
+    #[test]
+    fn test_sha1_and_base64() {
+        assert_eq!(base64(&sha1(b"abc")), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_check_valid_handshake() {
+        let mut map = headers(&UPGRADE);
+        map.append("Sec-WebSocket-Protocol", "chat, superchat")
+            .unwrap();
+
+        let handshake = check(&Method::Get, 1, &map).unwrap().unwrap();
+        assert_eq!(handshake.accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(handshake.protocols, vec!["chat", "superchat"]);
+    }
+
+    #[test]
+    fn test_check_invalid_handshakes() {
+        assert!(check(&Method::Get, 1, &headers(&UPGRADE[1..])).is_none());
+
+        let error = check(&Method::Post, 1, &headers(&UPGRADE))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.status, HttpStatus::BadRequest);
+
+        let mut old = headers(&UPGRADE);
+        old.insert("Sec-WebSocket-Version", "8").unwrap();
+        let error = check(&Method::Get, 1, &old).unwrap().unwrap_err();
+        assert_eq!(error.status, HttpStatus::UpgradeRequired);
+
+        let mut short_key = headers(&UPGRADE);
+        short_key.insert("Sec-WebSocket-Key", "c2hvcnQ=").unwrap();
+        let error = check(&Method::Get, 1, &short_key).unwrap().unwrap_err();
+        assert_eq!(error.status, HttpStatus::BadRequest);
+    }
+}
@@ -0,0 +1,634 @@
+//! WebSockets (RFC 6455) on top of an HTTP/1.1 connection.
+//!
+//! The server answers pings, reassembles fragmented messages and runs the
+//! closing handshake; the application sees whole messages only.
+
+mod frame;
+mod handshake;
+
+pub(crate) use handshake::{check, Handshake};
+
+use std::io::Result;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, timeout, Instant};
+use tracing::{debug, warn};
+
+use crate::core::addr::PeerAddr;
+use crate::core::enums::{HttpStatus, Timeout};
+use crate::core::events::Event;
+use crate::core::handle::ServerHandle;
+use crate::core::headers::HeaderMap;
+use crate::core::response::Response;
+use crate::core::structs::{ConnectionId, RequestId, ServerConfig};
+use crate::core::uri::Uri;
+use crate::core::websocket::{close_code, Message, WebSocketSend, WebSocketSender};
+use crate::protocols::tcp::connection::ByteStream;
+
+use frame::{FrameError, OP_BINARY, OP_CLOSE, OP_CONTINUATION, OP_PING, OP_PONG, OP_TEXT};
+
+/// The upgrade request, as reported in `Event::WebSocketConnect`.
+pub(crate) struct Upgrade {
+    pub(crate) request_id: RequestId,
+    pub(crate) uri: Uri,
+    pub(crate) headers: HeaderMap,
+    pub(crate) handshake: Handshake,
+}
+
+/// Where a WebSocket reports its events.
+pub(crate) struct Session<'a> {
+    pub(crate) connection_id: ConnectionId,
+    pub(crate) client_addr: &'a PeerAddr,
+    pub(crate) tx: &'a mpsc::Sender<Event>,
+    pub(crate) config: &'a ServerConfig,
+    pub(crate) handle: &'a ServerHandle,
+}
+
+/// Lets the application answer the handshake, then runs the WebSocket
+/// until either side closes it. `buffer` holds bytes the client sent
+/// after the upgrade request.
+pub(crate) async fn serve(
+    stream: &mut Box<dyn ByteStream>,
+    buffer: Vec<u8>,
+    upgrade: Upgrade,
+    session: Session<'_>,
+) {
+    let id = upgrade.request_id;
+    let (ws_tx, mut app_rx) = WebSocketSender::channel();
+    let _ = session
+        .tx
+        .send(Event::WebSocketConnect {
+            connection_id: session.connection_id,
+            request_id: id,
+            client_addr: session.client_addr.clone(),
+            uri: upgrade.uri,
+            headers: upgrade.headers,
+            protocols: upgrade.handshake.protocols.clone(),
+            ws_tx,
+        })
+        .await;
+
+    // 1. The application accepts or rejects the handshake, in the time it
+    //    has for any response.
+    let answer = timeout(session.config.response_timeout, app_rx.recv()).await;
+    let (protocol, headers) = match answer {
+        Ok(Some(WebSocketSend::Accept { protocol, headers })) => (protocol, headers),
+        Ok(Some(WebSocketSend::Reject(response))) => {
+            debug!(request = %id, status = response.status.code(), "WebSocket rejected");
+            let response = response.header("Connection", "close");
+            let _ = write_all(stream, &response.build()).await;
+            stream.close().await;
+            return;
+        }
+        Ok(Some(_)) | Ok(None) => {
+            let status = HttpStatus::Forbidden;
+            let _ = write_all(stream, &error_response(status)).await;
+            stream.close().await;
+            let _ = session
+                .tx
+                .send(Event::RequestRejected {
+                    connection_id: session.connection_id,
+                    request_id: Some(id),
+                    client_addr: session.client_addr.clone(),
+                    status,
+                    reason: "Logic dropped ws_tx without answering the handshake".to_string(),
+                })
+                .await;
+            return;
+        }
+        Err(_) => {
+            let _ = write_all(stream, &error_response(Timeout::Response.status())).await;
+            stream.close().await;
+            let _ = session
+                .tx
+                .send(Event::RequestTimeout {
+                    connection_id: session.connection_id,
+                    request_id: Some(id),
+                    client_addr: session.client_addr.clone(),
+                    timeout: Timeout::Response,
+                })
+                .await;
+            return;
+        }
+    };
+
+    let mut response = Response::new(HttpStatus::SwitchingProtocols)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &upgrade.handshake.accept);
+    if let Some(protocol) = protocol {
+        if upgrade.handshake.protocols.contains(&protocol) {
+            response = response.header("Sec-WebSocket-Protocol", &protocol);
+        } else {
+            warn!(request = %id, "Ignoring subprotocol {:?} the client did not offer", protocol);
+        }
+    }
+    for (name, value) in headers.iter() {
+        // CONDITION
+        // The handshake headers win, and a 101 has no body to frame.
+        let framing = name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding");
+        if !framing && !response.headers.contains(name) {
+            let _ = response.headers.append(name, value);
+        }
+    }
+    if let Err(e) = write_all(stream, &response.build_head()).await {
+        debug!("Failed to send 101 response: {:?}", e);
+        report_disconnect(&session, id, close_code::ABNORMAL, String::new()).await;
+        return;
+    }
+    debug!(request = %id, "WebSocket open");
+
+    // 2. Exchange messages until the closing handshake.
+    let (code, reason) = run(stream, buffer, app_rx, id, &session).await;
+    debug!(request = %id, code, "WebSocket closed");
+    stream.close().await;
+    report_disconnect(&session, id, code, reason).await;
+}
+
+/// A message being reassembled from fragments.
+struct Partial {
+    opcode: u8,
+    data: Vec<u8>,
+}
+
+/// Runs an open WebSocket. Returns the close code and reason to report.
+async fn run(
+    stream: &mut Box<dyn ByteStream>,
+    mut buffer: Vec<u8>,
+    mut app_rx: mpsc::Receiver<WebSocketSend>,
+    id: RequestId,
+    session: &Session<'_>,
+) -> (u16, String) {
+    let max_size = session.config.websocket_max_message_size;
+    let mut temp_buf = vec![0u8; session.config.read_buffer_size];
+    let mut partial: Option<Partial> = None;
+    // Set once our close frame is out: the code sent and when to stop
+    // waiting for the client's reply.
+    let mut closing: Option<(u16, String, Instant)> = None;
+
+    loop {
+        // 1. Handle every complete frame already read.
+        loop {
+            let (frame, used) = match frame::decode(&buffer, max_size) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => break,
+                Err(e) => return fail(stream, e).await,
+            };
+            buffer.drain(..used);
+
+            let message = match frame.opcode {
+                OP_CLOSE => {
+                    let (code, reason) = match frame::parse_close(&frame.payload) {
+                        Ok(close) => close,
+                        Err(e) => return fail(stream, e).await,
+                    };
+                    return match closing {
+                        // The reply to our close frame.
+                        Some((code, reason, _)) => (code, reason),
+                        None => {
+                            let echo = if code == close_code::NO_STATUS {
+                                close_code::NORMAL
+                            } else {
+                                code
+                            };
+                            let _ = write_frame(stream, OP_CLOSE, &frame::close_payload(echo, ""))
+                                .await;
+                            (code, reason)
+                        }
+                    };
+                }
+                OP_PING => {
+                    if closing.is_none() {
+                        if let Err(e) = write_frame(stream, OP_PONG, &frame.payload).await {
+                            debug!("Failed to send pong: {:?}", e);
+                            return (close_code::ABNORMAL, String::new());
+                        }
+                    }
+                    Message::Ping(frame.payload)
+                }
+                OP_PONG => Message::Pong(frame.payload),
+                opcode => {
+                    // CONDITION
+                    // IF fragments do not line up with the message they belong to.
+                    match (&mut partial, opcode) {
+                        (None, OP_TEXT | OP_BINARY) => {
+                            partial = Some(Partial {
+                                opcode,
+                                data: frame.payload,
+                            })
+                        }
+                        (Some(message), OP_CONTINUATION) => {
+                            message.data.extend_from_slice(&frame.payload)
+                        }
+                        _ => {
+                            let e = FrameError::protocol("unexpected continuation frame");
+                            return fail(stream, e).await;
+                        }
+                    }
+                    // CONDITION
+                    // IF the message grows past WEBSOCKET_MAX_MESSAGE_SIZE.
+                    if partial.as_ref().is_some_and(|m| m.data.len() > max_size) {
+                        let e = FrameError {
+                            code: close_code::MESSAGE_TOO_BIG,
+                            reason: "message too big",
+                        };
+                        return fail(stream, e).await;
+                    }
+                    if !frame.fin {
+                        continue;
+                    }
+                    let Partial { opcode, data } = partial.take().unwrap();
+                    if opcode == OP_BINARY {
+                        Message::Binary(data)
+                    } else {
+                        match String::from_utf8(data) {
+                            Ok(text) => Message::Text(text),
+                            Err(_) => {
+                                let e = FrameError {
+                                    code: close_code::INVALID_PAYLOAD,
+                                    reason: "text message is not UTF-8",
+                                };
+                                return fail(stream, e).await;
+                            }
+                        }
+                    }
+                }
+            };
+
+            let _ = session
+                .tx
+                .send(Event::WebSocketReceive {
+                    connection_id: session.connection_id,
+                    request_id: id,
+                    message,
+                })
+                .await;
+        }
+
+        let deadline = closing.as_ref().map(|(_, _, at)| *at);
+        tokio::select! {
+            // 2. Reading the socket.
+            read_result = stream.read(&mut temp_buf) => {
+                match read_result {
+                    Ok(0) => {
+                        debug!("Client disconnected without closing the WebSocket");
+                        return (close_code::ABNORMAL, String::new());
+                    }
+                    Ok(n) => buffer.extend_from_slice(&temp_buf[..n]),
+                    Err(e) => {
+                        debug!("Error while reading WebSocket: {:?}", e);
+                        return (close_code::ABNORMAL, String::new());
+                    }
+                }
+            }
+            // 3. Sending what the application asks for.
+            sent = app_rx.recv(), if closing.is_none() => {
+                let (opcode, payload) = match sent {
+                    Some(WebSocketSend::Message(Message::Text(text))) => (OP_TEXT, text.into_bytes()),
+                    Some(WebSocketSend::Message(Message::Binary(data))) => (OP_BINARY, data),
+                    Some(WebSocketSend::Message(Message::Ping(data))) => (OP_PING, data),
+                    Some(WebSocketSend::Message(Message::Pong(data))) => (OP_PONG, data),
+                    Some(WebSocketSend::Close { code, reason }) => {
+                        closing = Some(start_close(session.config, code, reason));
+                        (OP_CLOSE, frame::close_payload(code, &closing.as_ref().unwrap().1))
+                    }
+                    // Dropping the sender closes the WebSocket normally.
+                    None => {
+                        closing = Some(start_close(session.config, close_code::NORMAL, String::new()));
+                        (OP_CLOSE, frame::close_payload(close_code::NORMAL, ""))
+                    }
+                    Some(WebSocketSend::Accept { .. } | WebSocketSend::Reject(_)) => {
+                        warn!(request = %id, "WebSocket handshake answered twice");
+                        continue;
+                    }
+                };
+                if let Err(e) = write_frame(stream, opcode, &payload).await {
+                    debug!("Failed to send WebSocket frame: {:?}", e);
+                    return (close_code::ABNORMAL, String::new());
+                }
+            }
+            // 4. Closing, on shutdown.
+            _ = session.handle.wait(), if closing.is_none() => {
+                debug!(request = %id, "Closing WebSocket for shutdown");
+                let reason = "server shutting down".to_string();
+                let payload = frame::close_payload(close_code::GOING_AWAY, &reason);
+                closing = Some(start_close(session.config, close_code::GOING_AWAY, reason));
+                if write_frame(stream, OP_CLOSE, &payload).await.is_err() {
+                    return (close_code::ABNORMAL, String::new());
+                }
+            }
+            // 5. Giving up on a client that never answers our close frame.
+            _ = async {
+                match deadline {
+                    Some(at) => sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                debug!(request = %id, "No reply to the WebSocket close frame");
+                let (code, reason, _) = closing.take().unwrap();
+                return (code, reason);
+            }
+        }
+    }
+}
+
+/// Our close frame went out; the client's reply is awaited for the same
+/// time as the next piece of a request body.
+fn start_close(config: &ServerConfig, code: u16, reason: String) -> (u16, String, Instant) {
+    (code, reason, Instant::now() + config.body_read_timeout)
+}
+
+/// Closes the WebSocket because the client broke the protocol.
+async fn fail(stream: &mut Box<dyn ByteStream>, e: FrameError) -> (u16, String) {
+    warn!(code = e.code, "Failing WebSocket: {}", e.reason);
+    let _ = write_frame(stream, OP_CLOSE, &frame::close_payload(e.code, e.reason)).await;
+    (e.code, e.reason.to_string())
+}
+
+async fn write_frame(stream: &mut Box<dyn ByteStream>, opcode: u8, payload: &[u8]) -> Result<()> {
+    let mut data = Vec::with_capacity(payload.len() + 10);
+    frame::encode(opcode, payload, &mut data);
+    write_all(stream, &data).await
+}
+
+async fn write_all(stream: &mut Box<dyn ByteStream>, data: &[u8]) -> Result<()> {
+    stream.write_all(data).await?;
+    stream.flush().await
+}
+
+fn error_response(status: HttpStatus) -> Vec<u8> {
+    Response::error(status)
+        .header("Connection", "close")
+        .build()
+}
+
+async fn report_disconnect(session: &Session<'_>, id: RequestId, code: u16, reason: String) {
+    let _ = session
+        .tx
+        .send(Event::WebSocketDisconnect {
+            connection_id: session.connection_id,
+            request_id: id,
+            client_addr: session.client_addr.clone(),
+            code,
+            reason,
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::websocket::WebSocketError;
+    use crate::protocols::tcp::memory::{MemoryConnector, MemoryListener, TestClient};
+    use crate::protocols::tcp::server::run_server;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+
+    const HANDSHAKE: &[u8] = b"GET /chat HTTP/1.1\r\nHost: aegis.test\r\nUpgrade: websocket\r\n\
+        Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: chat\r\n\r\n";
+
+    /// Accepts every WebSocket and echoes its text and binary messages.
+    /// Text `close` makes the server close with 4000. Disconnects are
+    /// forwarded on the returned channel.
+    fn spawn_echo(config: ServerConfig) -> (MemoryConnector, mpsc::Receiver<(u16, String)>) {
+        let (listener, connector) = MemoryListener::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        let (closed_tx, closed_rx) = mpsc::channel(4);
+        tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config)));
+
+        tokio::spawn(async move {
+            let mut sockets: HashMap<RequestId, WebSocketSender> = HashMap::new();
+            while let Some(event) = rx.recv().await {
+                match event {
+                    Event::WebSocketConnect {
+                        request_id,
+                        protocols,
+                        mut ws_tx,
+                        ..
+                    } => {
+                        let protocol = protocols.first().map(String::as_str);
+                        ws_tx.accept(protocol).await.unwrap();
+                        sockets.insert(request_id, ws_tx);
+                    }
+                    Event::WebSocketReceive {
+                        request_id,
+                        message,
+                        ..
+                    } => {
+                        let ws_tx = sockets.get_mut(&request_id).unwrap();
+                        match message {
+                            Message::Text(text) if text == "close" => {
+                                ws_tx.close(4000, "asked to").await.unwrap()
+                            }
+                            Message::Text(_) | Message::Binary(_) => {
+                                ws_tx.send(message).await.unwrap()
+                            }
+                            _ => {}
+                        }
+                    }
+                    Event::WebSocketDisconnect {
+                        request_id,
+                        code,
+                        reason,
+                        ..
+                    } => {
+                        sockets.remove(&request_id);
+                        let _ = closed_tx.send((code, reason)).await;
+                    }
+                    _ => {}
+                }
+            }
+        });
+        (connector, closed_rx)
+    }
+
+    /// Connects and completes the handshake.
+    async fn open(connector: &MemoryConnector) -> DuplexStream {
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+        client.send(HANDSHAKE).await.unwrap();
+        let response = client.read_response().await.unwrap();
+        assert_eq!(response.status, HttpStatus::SwitchingProtocols);
+        assert_eq!(
+            response.headers.get_str("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(
+            response.headers.get_str("Sec-WebSocket-Protocol"),
+            Some("chat")
+        );
+        client.into_inner()
+    }
+
+    /// A masked client frame.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0xa1, 0xb2, 0xc3, 0xd4];
+        let mut out = Vec::new();
+        frame::encode(opcode, payload, &mut out);
+        let header_len = out.len() - payload.len();
+        out.truncate(header_len);
+        if !fin {
+            out[0] &= 0x7f;
+        }
+        out[1] |= 0x80;
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, k)| b ^ k));
+        out
+    }
+
+    /// Reads an unmasked server frame: opcode and payload.
+    async fn read_frame(io: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        io.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+        let length = match head[1] & 0x7f {
+            126 => io.read_u16().await.unwrap() as usize,
+            127 => io.read_u64().await.unwrap() as usize,
+            length => length as usize,
+        };
+        let mut payload = vec![0; length];
+        io.read_exact(&mut payload).await.unwrap();
+        (head[0] & 0x0f, payload)
+    }
+
+    #[tokio::test]
+    async fn test_echo_fragments_and_ping() {
+        let (connector, mut closed) = spawn_echo(ServerConfig {
+            websocket_max_message_size: 100_000,
+            ..ServerConfig::default()
+        });
+        let mut io = open(&connector).await;
+
+        // A text message in two fragments with a ping in between.
+        io.write_all(&client_frame(false, OP_TEXT, b"Hel"))
+            .await
+            .unwrap();
+        io.write_all(&client_frame(true, OP_PING, b"p"))
+            .await
+            .unwrap();
+        io.write_all(&client_frame(true, OP_CONTINUATION, b"lo"))
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut io).await, (OP_PONG, b"p".to_vec()));
+        assert_eq!(read_frame(&mut io).await, (OP_TEXT, b"Hello".to_vec()));
+
+        let large = vec![9u8; 70_000];
+        io.write_all(&client_frame(true, OP_BINARY, &large))
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut io).await, (OP_BINARY, large));
+
+        // Client-initiated close is echoed.
+        let close = frame::close_payload(1000, "done");
+        io.write_all(&client_frame(true, OP_CLOSE, &close))
+            .await
+            .unwrap();
+        let (opcode, payload) = read_frame(&mut io).await;
+        assert_eq!((opcode, &payload[..2]), (OP_CLOSE, &[0x03, 0xe8][..]));
+        assert_eq!(closed.recv().await, Some((1000, "done".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_server_initiated_close() {
+        let (connector, mut closed) = spawn_echo(ServerConfig::default());
+        let mut io = open(&connector).await;
+
+        io.write_all(&client_frame(true, OP_TEXT, b"close"))
+            .await
+            .unwrap();
+        let (opcode, payload) = read_frame(&mut io).await;
+        assert_eq!(opcode, OP_CLOSE);
+        assert_eq!(
+            frame::parse_close(&payload),
+            Ok((4000, "asked to".to_string()))
+        );
+
+        io.write_all(&client_frame(true, OP_CLOSE, &payload))
+            .await
+            .unwrap();
+        assert_eq!(closed.recv().await, Some((4000, "asked to".to_string())));
+        let mut rest = Vec::new();
+        io.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_message_size_limit() {
+        let (connector, mut closed) = spawn_echo(ServerConfig {
+            websocket_max_message_size: 8,
+            ..ServerConfig::default()
+        });
+        let mut io = open(&connector).await;
+
+        // Each fragment fits, the message does not.
+        io.write_all(&client_frame(false, OP_BINARY, b"12345"))
+            .await
+            .unwrap();
+        io.write_all(&client_frame(true, OP_CONTINUATION, b"6789"))
+            .await
+            .unwrap();
+        let (opcode, payload) = read_frame(&mut io).await;
+        assert_eq!(opcode, OP_CLOSE);
+        assert_eq!(
+            frame::parse_close(&payload).unwrap().0,
+            close_code::MESSAGE_TOO_BIG
+        );
+        assert_eq!(closed.recv().await.unwrap().0, close_code::MESSAGE_TOO_BIG);
+    }
+
+    #[tokio::test]
+    async fn test_protocol_errors_fail_the_connection() {
+        let (connector, mut closed) = spawn_echo(ServerConfig::default());
+
+        let mut io = open(&connector).await;
+        // Unmasked frame.
+        io.write_all(&[0x81, 0x01, b'x']).await.unwrap();
+        assert_eq!(read_frame(&mut io).await.0, OP_CLOSE);
+        assert_eq!(closed.recv().await.unwrap().0, close_code::PROTOCOL_ERROR);
+
+        let mut io = open(&connector).await;
+        io.write_all(&client_frame(true, OP_TEXT, &[0xff, 0xfe]))
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut io).await.0, OP_CLOSE);
+        assert_eq!(closed.recv().await.unwrap().0, close_code::INVALID_PAYLOAD);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_answers() {
+        let (listener, connector) = MemoryListener::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(run_server(
+            Box::new(listener),
+            tx,
+            Arc::new(ServerConfig::default()),
+        ));
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Event::WebSocketConnect { mut ws_tx, .. } = event {
+                    assert_eq!(ws_tx.text("early").await, Err(WebSocketError::NotAccepted));
+                    let denied = Response::error(HttpStatus::Unauthorized);
+                    ws_tx.reject(denied).await.unwrap();
+                }
+            }
+        });
+
+        // Rejected by the application.
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+        let response = client.request(HANDSHAKE).await.unwrap();
+        assert_eq!(response.status, HttpStatus::Unauthorized);
+        assert!(response.closes_connection());
+
+        // Unsupported version, answered by the server.
+        let mut client = TestClient::new(connector.connect().await.unwrap());
+        let old = String::from_utf8_lossy(HANDSHAKE).replace("Version: 13", "Version: 8");
+        let response = client.request(old.as_bytes()).await.unwrap();
+        assert_eq!(response.status, HttpStatus::UpgradeRequired);
+        assert_eq!(
+            response.headers.get_str("Sec-WebSocket-Version"),
+            Some("13")
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use async_trait::async_trait;
+use std::future::Future;
+
+use crate::core::response::Response;
+
+use super::request::Request;
+use super::websocket::WebSocket;
+
+/// Application logic answering one request at a time.
+///
+/// Every request runs in its own task, so `call` may be invoked
+/// concurrently. Plain `async fn(Request) -> Response` closures implement
+/// it as well.
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    async fn call(&self, request: Request) -> Response;
+
+    /// Runs before the server accepts connections, e.g. to open database
+    /// pools. An error aborts `Server::run`.
+    async fn startup(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs after the server drained its connections.
+    async fn shutdown(&self) {}
+
+    /// Serves a WebSocket upgrade request, in its own task like `call`.
+    /// The default rejects it with `404 Not Found`.
+    async fn websocket(&self, socket: WebSocket) {
+        let _ = socket.reject(Response::not_found()).await;
+    }
+}
+
+#[async_trait]
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    async fn call(&self, request: Request) -> Response {
+        (self)(request).await
+    }
+}
@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+use crate::core::enums::HttpStatus;
+use crate::core::response::Response;
+
+use super::handler::Handler;
+use super::request::Request;
+
+/// Logic wrapped around request handling, such as auth or logging.
+///
+/// A middleware receives the request and the rest of the chain. It may
+/// change the request before calling `next.run`, change the response it
+/// gets back, or answer on its own without calling `next` at all. Plain
+/// `async fn(Request, Next) -> Response` closures implement it as well.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    async fn handle(&self, request: Request, next: Next) -> Response;
+}
+
+#[async_trait]
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    async fn handle(&self, request: Request, next: Next) -> Response {
+        (self)(request, next).await
+    }
+}
+
+enum Endpoint {
+    Handler(Arc<dyn Handler>),
+    // A response decided before the chain ran, e.g. the router's 404.
+    Response(Response),
+    // A WebSocket upgrade: the request is handed back to be upgraded.
+    Upgrade(oneshot::Sender<Request>),
+}
+
+/// The remainder of a middleware chain, ending in the handler.
+pub struct Next {
+    chain: Vec<Arc<dyn Middleware>>,
+    index: usize,
+    endpoint: Endpoint,
+}
+
+impl Next {
+    pub(crate) fn handler(chain: Vec<Arc<dyn Middleware>>, handler: Arc<dyn Handler>) -> Self {
+        Self {
+            chain,
+            index: 0,
+            endpoint: Endpoint::Handler(handler),
+        }
+    }
+
+    pub(crate) fn response(chain: Vec<Arc<dyn Middleware>>, response: Response) -> Self {
+        Self {
+            chain,
+            index: 0,
+            endpoint: Endpoint::Response(response),
+        }
+    }
+
+    pub(crate) fn upgrade(chain: Vec<Arc<dyn Middleware>>, tx: oneshot::Sender<Request>) -> Self {
+        Self {
+            chain,
+            index: 0,
+            endpoint: Endpoint::Upgrade(tx),
+        }
+    }
+
+    /// Passes the request on to the next middleware, or to the handler
+    /// once the chain is exhausted.
+    pub async fn run(mut self, request: Request) -> Response {
+        match self.chain.get(self.index) {
+            Some(middleware) => {
+                let middleware = Arc::clone(middleware);
+                self.index += 1;
+                middleware.handle(request, self).await
+            }
+            None => match self.endpoint {
+                Endpoint::Handler(handler) => handler.call(request).await,
+                Endpoint::Response(response) => response,
+                Endpoint::Upgrade(tx) => {
+                    let _ = tx.send(request);
+                    Response::new(HttpStatus::SwitchingProtocols)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::{HttpStatus, Method};
+    use crate::core::uri::Uri;
+
+    fn tag(name: &'static str) -> impl Middleware {
+        move |mut request: Request, next: Next| async move {
+            request.headers.append("X-Seen", name).unwrap();
+            let response = next.run(request).await;
+            response.append_header("X-Order", name)
+        }
+    }
+
+    fn echo_seen() -> Arc<dyn Handler> {
+        Arc::new(|request: Request| async move {
+            let seen: Vec<_> = request
+                .headers
+                .get_all("X-Seen")
+                .map(|v| String::from_utf8_lossy(v).into_owned())
+                .collect();
+            Response::ok().body(seen.join(",").into_bytes())
+        })
+    }
+
+    fn request() -> Request {
+        Request::new(Method::Get, Uri::parse("/").unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_chain_runs_in_order() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(tag("outer")), Arc::new(tag("inner"))];
+        let response = Next::handler(chain, echo_seen()).run(request()).await;
+
+        // Requests pass outer to inner, responses inner to outer.
+        assert_eq!(response.body, b"outer,inner");
+        let order: Vec<_> = response.headers.get_all("X-Order").collect();
+        assert_eq!(order, vec![&b"inner"[..], &b"outer"[..]]);
+    }
+
+    #[tokio::test]
+    async fn test_short_circuit() {
+        let deny =
+            |_request: Request, _next: Next| async { Response::error(HttpStatus::Forbidden) };
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(deny), Arc::new(tag("never"))];
+        let response = Next::handler(chain, echo_seen()).run(request()).await;
+
+        assert_eq!(response.status, HttpStatus::Forbidden);
+        assert!(!response.has_header("X-Order"));
+    }
+
+    #[tokio::test]
+    async fn test_fixed_response_endpoint() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(tag("logger"))];
+        let response = Next::response(chain, Response::not_found())
+            .run(request())
+            .await;
+
+        assert_eq!(response.status, HttpStatus::NotFound);
+        assert_eq!(response.headers.get_str("X-Order"), Some("logger"));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod handler;
+pub mod middleware;
+pub mod request;
+pub mod router;
+pub mod server;
+pub mod websocket;
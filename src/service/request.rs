@@ -0,0 +1,214 @@
+use std::fmt;
+use tokio::sync::mpsc;
+
+use crate::core::addr::PeerAddr;
+use crate::core::enums::Method;
+use crate::core::headers::HeaderMap;
+use crate::core::response::ResponseMessage;
+use crate::core::sse;
+use crate::core::structs::{ConnectionId, RequestId, RequestMeta};
+use crate::core::uri::Uri;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyError {
+    /// The client went away before the whole body arrived.
+    Disconnected,
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "client disconnected before the body was complete"),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// Sending half of a [`Body`], fed by the dispatcher from `RequestBody` events.
+pub(crate) type BodySender = mpsc::UnboundedSender<(Vec<u8>, bool)>;
+
+/// Request body delivered to a handler in the pieces it arrived in.
+#[derive(Debug)]
+pub struct Body {
+    // Bytes that arrived together with the request head.
+    first: Option<Vec<u8>>,
+    rx: Option<mpsc::UnboundedReceiver<(Vec<u8>, bool)>>,
+    complete: bool,
+    // Sends `100 Continue` on the first read, for `Expect: 100-continue`.
+    continue_tx: Option<mpsc::WeakSender<ResponseMessage>>,
+}
+
+impl Body {
+    /// A body whose bytes are all known up front.
+    pub fn full(data: Vec<u8>) -> Self {
+        Self {
+            first: Some(data),
+            rx: None,
+            complete: true,
+            continue_tx: None,
+        }
+    }
+
+    /// A body starting with `first` and continued through the returned sender.
+    pub(crate) fn channel(first: Vec<u8>) -> (BodySender, Self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let body = Self {
+            first: Some(first),
+            rx: Some(rx),
+            complete: false,
+            continue_tx: None,
+        };
+        (tx, body)
+    }
+
+    /// Asks the client for the body through `tx` once it is first read,
+    /// so a handler that answers without reading it never receives it.
+    pub(crate) fn send_continue_on_read(&mut self, tx: mpsc::WeakSender<ResponseMessage>) {
+        self.continue_tx = Some(tx);
+    }
+
+    /// Next piece of the body, `Ok(None)` once it has been fully read.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, BodyError> {
+        if let Some(first) = self.first.take() {
+            if !first.is_empty() {
+                return Ok(Some(first));
+            }
+        }
+        if self.complete {
+            return Ok(None);
+        }
+        let Some(rx) = self.rx.as_mut() else {
+            return Ok(None);
+        };
+        if let Some(tx) = self.continue_tx.take().and_then(|tx| tx.upgrade()) {
+            let _ = tx.send(ResponseMessage::Continue).await;
+        }
+
+        match rx.recv().await {
+            Some((data, more_body)) => {
+                self.complete = !more_body;
+                Ok(Some(data))
+            }
+            None => Err(BodyError::Disconnected),
+        }
+    }
+
+    /// Reads the remaining body into one buffer.
+    pub async fn collect(mut self) -> Result<Vec<u8>, BodyError> {
+        let mut out = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            out.extend_from_slice(&chunk);
+        }
+        Ok(out)
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::full(Vec::new())
+    }
+}
+
+/// Values captured from the path by route patterns such as `/users/:id`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams(pub Vec<(String, String)>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A request as seen by a [`Handler`](super::handler::Handler).
+#[derive(Debug)]
+pub struct Request {
+    pub connection_id: ConnectionId,
+    pub request_id: RequestId,
+    pub client_addr: PeerAddr,
+    pub method: Method,
+    pub uri: Uri,
+    pub version: u8,
+    pub headers: HeaderMap,
+    pub meta: RequestMeta,
+    // Filled in by the router; empty otherwise.
+    pub params: PathParams,
+    pub body: Body,
+}
+
+impl Request {
+    /// A request with an empty body and placeholder connection details,
+    /// mainly for calling handlers directly in tests.
+    pub fn new(method: Method, uri: Uri) -> Self {
+        Self {
+            connection_id: ConnectionId(0),
+            request_id: RequestId(0),
+            client_addr: PeerAddr::Tcp(([0, 0, 0, 0], 0).into()),
+            method,
+            uri,
+            version: 1,
+            headers: HeaderMap::new(),
+            meta: RequestMeta::new(),
+            params: PathParams::default(),
+            body: Body::default(),
+        }
+    }
+
+    /// The `Last-Event-ID` a client reconnecting to an event stream sent,
+    /// to resume after the last event it saw.
+    pub fn last_event_id(&self) -> Option<&str> {
+        sse::last_event_id(&self.headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_full_body() {
+        let body = Body::full(b"abc".to_vec());
+        assert_eq!(body.collect().await.unwrap(), b"abc");
+
+        assert!(Body::default().collect().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body() {
+        let (tx, mut body) = Body::channel(b"ab".to_vec());
+        tx.send((b"cd".to_vec(), true)).unwrap();
+        tx.send((b"ef".to_vec(), false)).unwrap();
+
+        assert_eq!(body.chunk().await.unwrap(), Some(b"ab".to_vec()));
+        assert_eq!(body.collect().await.unwrap(), b"cdef");
+    }
+
+    #[tokio::test]
+    async fn test_first_read_sends_continue() {
+        let (resp_tx, mut resp_rx) = crate::core::response::ResponseSender::channel();
+        let (tx, mut body) = Body::channel(Vec::new());
+        body.send_continue_on_read(resp_tx.downgrade());
+        tx.send((b"abc".to_vec(), false)).unwrap();
+
+        assert!(resp_rx.try_recv().is_err());
+        assert_eq!(body.collect().await.unwrap(), b"abc");
+        assert!(matches!(resp_rx.try_recv(), Ok(ResponseMessage::Continue)));
+        assert!(resp_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_body() {
+        let (tx, body) = Body::channel(Vec::new());
+        tx.send((b"partial".to_vec(), true)).unwrap();
+        drop(tx);
+
+        assert_eq!(body.collect().await, Err(BodyError::Disconnected));
+    }
+}
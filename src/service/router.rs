@@ -0,0 +1,587 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+use crate::core::enums::{HttpStatus, Method};
+use crate::core::response::Response;
+use crate::core::uri::Uri;
+
+use super::handler::Handler;
+use super::middleware::{Middleware, Next};
+use super::request::{PathParams, Request};
+use super::websocket::WebSocket;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    // `:name`, matches exactly one segment.
+    Param(String),
+    // `*name`, matches the rest of the path, possibly nothing.
+    Wildcard(String),
+}
+
+/// A parsed route pattern such as `/users/:id/files/*path`.
+#[derive(Debug, Clone)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    /// Panics on a wildcard that is not the last segment, since that is a
+    /// mistake in the route table rather than in a request.
+    fn parse(pattern: &str) -> Self {
+        let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                if let Some(name) = part.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        i == parts.len() - 1,
+                        "wildcard must be the last segment in {:?}",
+                        pattern
+                    );
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Static(part.to_string())
+                }
+            })
+            .collect();
+        Self { segments }
+    }
+
+    /// Matches the start of `path`, returning how many segments were
+    /// consumed. A wildcard consumes everything.
+    fn match_prefix(&self, path: &[String], params: &mut Params) -> Option<usize> {
+        let start = params.len();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Wildcard(name) => {
+                    if !name.is_empty() {
+                        params.push((name.clone(), path[i.min(path.len())..].join("/")));
+                    }
+                    return Some(path.len());
+                }
+                _ if i >= path.len() => {
+                    params.truncate(start);
+                    return None;
+                }
+                Segment::Static(expected) if *expected != path[i] => {
+                    params.truncate(start);
+                    return None;
+                }
+                Segment::Static(_) => {}
+                Segment::Param(name) => params.push((name.clone(), path[i].clone())),
+            }
+        }
+        Some(self.segments.len())
+    }
+
+    fn matches(&self, path: &[String], params: &mut Params) -> bool {
+        let start = params.len();
+        match self.match_prefix(path, params) {
+            Some(consumed) if consumed == path.len() => true,
+            Some(_) => {
+                params.truncate(start);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+// Captured `(name, value)` pairs, in pattern order.
+type Params = Vec<(String, String)>;
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Arc<dyn Handler>,
+}
+
+enum Lookup<'a> {
+    // The handler and the middleware of every router on the way to it.
+    Found(&'a Arc<dyn Handler>, Params, Vec<Arc<dyn Middleware>>),
+    // The path exists, but not for this method.
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+/// Dispatches requests by method and path.
+///
+/// Patterns are matched against the percent-decoded path segments:
+/// `:name` captures one segment, `*name` captures the rest of the path.
+/// Routes are tried in registration order, before any mounted routers.
+/// Unmatched paths get `404 Not Found`, known paths with another method
+/// `405 Method Not Allowed` with an `Allow` header, and `OPTIONS` is
+/// answered automatically unless a route handles it. `HEAD` falls back to
+/// the `GET` route.
+///
+/// Middleware added with [`layer`](Router::layer) wraps every request the
+/// router answers, including the automatic responses. Middleware of a
+/// mounted router only wraps the routes inside it and runs after that of
+/// the routers it is mounted in.
+///
+/// WebSocket upgrades are routed like `GET` requests and run through the
+/// same middleware. If the middleware lets the request through, the
+/// route's [`Handler::websocket`] takes over, and headers the middleware
+/// added to the `101` response are sent with the handshake. If it answers
+/// on its own, the upgrade is rejected with that response.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    mounts: Vec<(Pattern, Router)>,
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Handler) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Adds middleware to this router. Middleware runs in the order it was
+    /// added, the first one seeing the request first and the response last.
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Serves `router` below `prefix`. The prefix may contain parameters,
+    /// which are visible to the nested routes.
+    pub fn mount(mut self, prefix: &str, router: Router) -> Self {
+        self.mounts.push((Pattern::parse(prefix), router));
+        self
+    }
+
+    fn lookup(&self, method: &Method, path: &[String]) -> Lookup<'_> {
+        let mut allowed = Vec::new();
+        let mut chain = self.layers.clone();
+        if let Some((handler, params)) =
+            self.find(method, path, Vec::new(), &mut chain, &mut allowed)
+        {
+            return Lookup::Found(handler, params, chain);
+        }
+        if *method == Method::Head {
+            if let Some((handler, params)) =
+                self.find(&Method::Get, path, Vec::new(), &mut chain, &mut allowed)
+            {
+                return Lookup::Found(handler, params, chain);
+            }
+        }
+
+        if allowed.is_empty() {
+            Lookup::NotFound
+        } else {
+            Lookup::MethodNotAllowed(allowed)
+        }
+    }
+
+    /// Finds the handler for `method`, collecting the methods of every
+    /// route whose pattern matches into `allowed`. The middleware of the
+    /// mounted routers leading to the handler is appended to `chain`.
+    fn find(
+        &self,
+        method: &Method,
+        path: &[String],
+        params: Params,
+        chain: &mut Vec<Arc<dyn Middleware>>,
+        allowed: &mut Vec<Method>,
+    ) -> Option<(&Arc<dyn Handler>, Params)> {
+        for route in &self.routes {
+            let mut route_params = params.clone();
+            if !route.pattern.matches(path, &mut route_params) {
+                continue;
+            }
+            if route.method == *method {
+                return Some((&route.handler, route_params));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+        }
+
+        for (prefix, router) in &self.mounts {
+            let mut mount_params = params.clone();
+            if let Some(consumed) = prefix.match_prefix(path, &mut mount_params) {
+                let outer = chain.len();
+                chain.extend(router.layers.iter().cloned());
+                let found = router.find(method, &path[consumed..], mount_params, chain, allowed);
+                if found.is_some() {
+                    return found;
+                }
+                chain.truncate(outer);
+            }
+        }
+        None
+    }
+}
+
+/// Value of the `Allow` header for the given route methods.
+fn allow_header(methods: &[Method]) -> String {
+    let mut names: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+        names.push("HEAD");
+    }
+    if !methods.contains(&Method::Options) {
+        names.push("OPTIONS");
+    }
+    names.join(", ")
+}
+
+#[async_trait]
+impl Handler for Router {
+    async fn call(&self, mut request: Request) -> Response {
+        let segments = std::mem::take(&mut request.uri.segments);
+        let lookup = self.lookup(&request.method, &segments);
+        request.uri.segments = segments;
+
+        let response = match lookup {
+            Lookup::Found(handler, params, chain) => {
+                request.params = PathParams(params);
+                return Next::handler(chain, Arc::clone(handler)).run(request).await;
+            }
+            Lookup::MethodNotAllowed(methods) if request.method == Method::Options => {
+                Response::new(HttpStatus::NoContent).header("Allow", &allow_header(&methods))
+            }
+            Lookup::MethodNotAllowed(methods) => Response::error(HttpStatus::MethodNotAllowed)
+                .header("Allow", &allow_header(&methods)),
+            Lookup::NotFound => Response::error(HttpStatus::NotFound),
+        };
+        Next::response(self.layers.clone(), response)
+            .run(request)
+            .await
+    }
+
+    async fn websocket(&self, mut socket: WebSocket) {
+        let placeholder = Request::new(Method::Get, Uri::default());
+        let mut request = std::mem::replace(&mut socket.request, placeholder);
+        let segments = std::mem::take(&mut request.uri.segments);
+        let lookup = self.lookup(&Method::Get, &segments);
+        request.uri.segments = segments;
+
+        // 1. Unknown paths get the same 404 or 405 a request would.
+        let Lookup::Found(handler, params, chain) = lookup else {
+            let response = self.call(request).await;
+            let _ = socket.reject(response).await;
+            return;
+        };
+
+        // 2. Running the middleware, which hands the request back unless
+        //    it answers on its own. Headers it adds to the 101 it gets back
+        //    are kept for the handshake.
+        request.params = PathParams(params);
+        let (tx, mut rx) = oneshot::channel();
+        let response = Next::upgrade(chain, tx).run(request).await;
+        match rx.try_recv() {
+            Ok(request) => {
+                socket.request = request;
+                socket.handshake_headers(response.headers);
+                handler.websocket(socket).await;
+            }
+            Err(_) => {
+                let _ = socket.reject(response).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::websocket::{Message, WebSocketSend, WebSocketSender};
+    use tokio::sync::mpsc;
+
+    fn request(method: Method, target: &str) -> Request {
+        Request::new(method, Uri::parse(target).unwrap())
+    }
+
+    fn named(name: &'static str) -> impl Handler {
+        move |request: Request| async move {
+            let mut body = name.to_string();
+            for (key, value) in &request.params.0 {
+                body.push_str(&format!(" {}={}", key, value));
+            }
+            Response::ok().body(body.into_bytes())
+        }
+    }
+
+    async fn call(router: &Router, method: Method, target: &str) -> Response {
+        router.call(request(method, target)).await
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_static_and_param_routes() {
+        let router = Router::new()
+            .get("/", named("root"))
+            .get("/users/me", named("me"))
+            .get("/users/:id", named("user"))
+            .get("/users/:id/posts/:post", named("post"));
+
+        assert_eq!(body(&call(&router, Method::Get, "/").await), "root");
+        assert_eq!(body(&call(&router, Method::Get, "/users/me").await), "me");
+        assert_eq!(
+            body(&call(&router, Method::Get, "/users/42").await),
+            "user id=42"
+        );
+        assert_eq!(
+            body(&call(&router, Method::Get, "/users/a%20b/posts/7?x=1").await),
+            "post id=a b post=7"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wildcard() {
+        let router = Router::new().get("/static/*path", named("static"));
+
+        assert_eq!(
+            body(&call(&router, Method::Get, "/static/css/site.css").await),
+            "static path=css/site.css"
+        );
+        assert_eq!(
+            body(&call(&router, Method::Get, "/static").await),
+            "static path="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let router = Router::new().get("/users/:id", named("user"));
+
+        let response = call(&router, Method::Get, "/users/1/extra").await;
+        assert_eq!(response.status, HttpStatus::NotFound);
+        let response = call(&router, Method::Get, "/nothing").await;
+        assert_eq!(response.status, HttpStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed_lists_methods() {
+        let router = Router::new()
+            .get("/items", named("list"))
+            .post("/items", named("create"));
+
+        let response = call(&router, Method::Delete, "/items").await;
+        assert_eq!(response.status, HttpStatus::MethodNotAllowed);
+        assert_eq!(
+            response.headers.get_str("Allow"),
+            Some("GET, POST, HEAD, OPTIONS")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_automatic_options_and_head() {
+        let router = Router::new().get("/items", named("list")).route(
+            Method::Options,
+            "/custom",
+            named("custom options"),
+        );
+
+        let response = call(&router, Method::Options, "/items").await;
+        assert_eq!(response.status, HttpStatus::NoContent);
+        assert_eq!(
+            response.headers.get_str("Allow"),
+            Some("GET, HEAD, OPTIONS")
+        );
+
+        let response = call(&router, Method::Options, "/custom").await;
+        assert_eq!(body(&response), "custom options");
+
+        let response = call(&router, Method::Head, "/items").await;
+        assert_eq!(body(&response), "list");
+    }
+
+    #[tokio::test]
+    async fn test_mounts() {
+        let users = Router::new()
+            .get("/", named("users"))
+            .get("/:id", named("user"));
+        let router = Router::new()
+            .get("/health", named("health"))
+            .mount("/api/:version/users", users);
+
+        assert_eq!(
+            body(&call(&router, Method::Get, "/api/v1/users").await),
+            "users version=v1"
+        );
+        assert_eq!(
+            body(&call(&router, Method::Get, "/api/v2/users/9").await),
+            "user version=v2 id=9"
+        );
+
+        let response = call(&router, Method::Post, "/api/v1/users/9").await;
+        assert_eq!(response.status, HttpStatus::MethodNotAllowed);
+        let response = call(&router, Method::Get, "/api/v1/other").await;
+        assert_eq!(response.status, HttpStatus::NotFound);
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn test_wildcard_must_be_last() {
+        Router::new().get("/*rest/more", named("bad"));
+    }
+
+    #[tokio::test]
+    async fn test_layers_per_mount() {
+        fn tag(name: &'static str) -> impl Middleware {
+            move |request: Request, next: Next| async move {
+                next.run(request).await.append_header("X-Layer", name)
+            }
+        }
+        fn layers(response: &Response) -> Vec<&[u8]> {
+            response.headers.get_all("X-Layer").collect()
+        }
+
+        let admin = Router::new()
+            .get("/:page", named("admin"))
+            .layer(tag("admin"));
+        let router = Router::new()
+            .get("/", named("root"))
+            .mount("/admin", admin)
+            .layer(tag("root"));
+
+        let response = call(&router, Method::Get, "/admin/users").await;
+        assert_eq!(body(&response), "admin page=users");
+        assert_eq!(layers(&response), vec![&b"admin"[..], &b"root"[..]]);
+
+        let response = call(&router, Method::Get, "/").await;
+        assert_eq!(layers(&response), vec![&b"root"[..]]);
+
+        // Automatic responses only pass through the outer router's layers.
+        let response = call(&router, Method::Get, "/missing/page/here").await;
+        assert_eq!(response.status, HttpStatus::NotFound);
+        assert_eq!(layers(&response), vec![&b"root"[..]]);
+    }
+
+    #[tokio::test]
+    async fn test_layer_sees_params_and_can_short_circuit() {
+        let only_admin = |request: Request, next: Next| async move {
+            if request.params.get("user") != Some("admin") {
+                return Response::error(HttpStatus::Forbidden);
+            }
+            next.run(request).await
+        };
+        let router = Router::new()
+            .get("/users/:user/secret", named("secret"))
+            .layer(only_admin);
+
+        let response = call(&router, Method::Get, "/users/bob/secret").await;
+        assert_eq!(response.status, HttpStatus::Forbidden);
+        let response = call(&router, Method::Get, "/users/admin/secret").await;
+        assert_eq!(body(&response), "secret user=admin");
+    }
+
+    /// Accepts the upgrade and sends the `room` parameter.
+    struct Room;
+
+    #[async_trait]
+    impl Handler for Room {
+        async fn call(&self, _request: Request) -> Response {
+            Response::ok()
+        }
+
+        async fn websocket(&self, mut socket: WebSocket) {
+            let room = socket.request.params.get("room").unwrap_or("").to_string();
+            socket.accept(None).await.unwrap();
+            socket.send(Message::Text(room)).await.unwrap();
+        }
+    }
+
+    async fn upgrade(router: &Router, target: &str) -> mpsc::Receiver<WebSocketSend> {
+        let (ws_tx, ws_rx) = WebSocketSender::channel();
+        let (_messages, socket) = WebSocket::new(request(Method::Get, target), Vec::new(), ws_tx);
+        router.websocket(socket).await;
+        ws_rx
+    }
+
+    #[tokio::test]
+    async fn test_websocket_upgrades_are_routed() {
+        let router = Router::new().mount("/chat", Router::new().get("/:room", Room));
+
+        let mut ws_rx = upgrade(&router, "/chat/lobby").await;
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Accept { .. })
+        ));
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Message(Message::Text(room))) if room == "lobby"
+        ));
+
+        let mut ws_rx = upgrade(&router, "/elsewhere").await;
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Reject(response)) if response.status == HttpStatus::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_upgrades_run_middleware() {
+        let only_admin = |request: Request, next: Next| async move {
+            if request.params.get("room") != Some("admin") {
+                return Response::error(HttpStatus::Forbidden);
+            }
+            next.run(request).await
+        };
+        let router = Router::new().get("/:room", Room).layer(only_admin);
+
+        let mut ws_rx = upgrade(&router, "/lobby").await;
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Reject(response)) if response.status == HttpStatus::Forbidden
+        ));
+
+        let mut ws_rx = upgrade(&router, "/admin").await;
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Accept { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_handshake_keeps_middleware_headers() {
+        let cookie = |request: Request, next: Next| async move {
+            next.run(request).await.header("Set-Cookie", "seen=1")
+        };
+        let router = Router::new().get("/:room", Room).layer(cookie);
+
+        let mut ws_rx = upgrade(&router, "/lobby").await;
+        let Some(WebSocketSend::Accept { headers, .. }) = ws_rx.recv().await else {
+            panic!("expected Accept");
+        };
+        assert_eq!(headers.get_str("Set-Cookie"), Some("seen=1"));
+    }
+}
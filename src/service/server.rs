@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::core::addr::ServerAddr;
+use crate::core::enums::Method;
+use crate::core::events::Event;
+use crate::core::handle::ServerHandle;
+use crate::core::structs::{RequestId, RequestMeta, ServerConfig};
+#[cfg(unix)]
+use crate::protocols::tcp::listener::UnixByteListener;
+use crate::protocols::tcp::listener::{Listener, TcpByteListener};
+use crate::protocols::tcp::server::run_server_with_handle;
+#[cfg(feature = "tls")]
+use crate::protocols::tls::listener::TlsListener;
+
+use super::handler::Handler;
+use super::request::{Body, BodySender, PathParams, Request};
+use super::websocket::{MessageSender, WebSocket};
+
+// Capacity of the channel between connection tasks and the dispatcher.
+const DEFAULT_EVENT_BUFFER: usize = 100;
+
+#[derive(Debug)]
+pub enum BuildError {
+    MissingHandler,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHandler => write!(f, "no handler configured"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// A listener, configuration and handler wired together.
+///
+/// ```no_run
+/// # use aegis::core::response::Response;
+/// # use aegis::service::request::Request;
+/// # use aegis::service::server::Server;
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// Server::builder()
+///     .handler(|_request: Request| async { Response::ok() })
+///     .build()?
+///     .run()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    listener: Option<Box<dyn Listener>>,
+    config: Arc<ServerConfig>,
+    handler: Arc<dyn Handler>,
+    event_buffer: usize,
+    handle: ServerHandle,
+}
+
+#[derive(Default)]
+pub struct ServerBuilder {
+    listener: Option<Box<dyn Listener>>,
+    config: Option<ServerConfig>,
+    handler: Option<Arc<dyn Handler>>,
+    event_buffer: Option<usize>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Handle to shut the server down gracefully and read its counters.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Runs the handler's startup hook, then accepts connections and
+    /// dispatches requests to the handler until the listener fails or the
+    /// server is shut down.
+    pub async fn run(self) -> tokio::io::Result<()> {
+        let listener: Box<dyn Listener> = match self.listener {
+            Some(listener) => listener,
+            None => {
+                let listener: Box<dyn Listener> = match &self.config.addr {
+                    ServerAddr::Tcp(addr) => Box::new(TcpByteListener::bind(*addr).await?),
+                    #[cfg(unix)]
+                    ServerAddr::Unix(path) => {
+                        Box::new(UnixByteListener::bind(path, self.config.unix_socket_mode).await?)
+                    }
+                    #[cfg(not(unix))]
+                    ServerAddr::Unix(_) => {
+                        return Err(tokio::io::Error::new(
+                            tokio::io::ErrorKind::Unsupported,
+                            "Unix sockets are not supported on this platform",
+                        ))
+                    }
+                };
+                with_tls(listener, &self.config)?
+            }
+        };
+
+        let (tx, rx) = mpsc::channel(self.event_buffer);
+        tokio::spawn(dispatch(rx, self.handler));
+
+        run_server_with_handle(listener, tx, self.config, self.handle).await
+    }
+}
+
+/// Terminates TLS on `listener` when `config` has certificates.
+fn with_tls(
+    listener: Box<dyn Listener>,
+    config: &ServerConfig,
+) -> tokio::io::Result<Box<dyn Listener>> {
+    if !config.tls_enabled() {
+        return Ok(listener);
+    }
+    #[cfg(feature = "tls")]
+    {
+        Ok(Box::new(TlsListener::new(listener, config)?))
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        Err(tokio::io::Error::new(
+            tokio::io::ErrorKind::Unsupported,
+            "TLS certificates are set but aegis was built without the tls feature",
+        ))
+    }
+}
+
+impl ServerBuilder {
+    /// Listener to accept connections from, used as is. Defaults to a TCP
+    /// or Unix socket listener on `ServerConfig::addr`, with TLS when
+    /// `ServerConfig` has certificates.
+    pub fn listener(mut self, listener: impl Listener + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Defaults to `ServerConfig::from_env()`.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn handler(mut self, handler: impl Handler) -> Self {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn event_buffer(mut self, capacity: usize) -> Self {
+        self.event_buffer = Some(capacity);
+        self
+    }
+
+    pub fn build(self) -> Result<Server, BuildError> {
+        let handler = self.handler.ok_or(BuildError::MissingHandler)?;
+        let config = self.config.unwrap_or_else(ServerConfig::from_env);
+
+        Ok(Server {
+            listener: self.listener,
+            config: Arc::new(config),
+            handler,
+            event_buffer: self.event_buffer.unwrap_or(DEFAULT_EVENT_BUFFER),
+            handle: ServerHandle::new(),
+        })
+    }
+}
+
+/// Turns connection events into handler calls, one task per request.
+async fn dispatch(mut rx: mpsc::Receiver<Event>, handler: Arc<dyn Handler>) {
+    // Body senders of requests whose body is still arriving.
+    let mut bodies: HashMap<RequestId, BodySender> = HashMap::new();
+    // Message senders of open WebSockets.
+    let mut sockets: HashMap<RequestId, MessageSender> = HashMap::new();
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            Event::Startup { ack } => match handler.startup().await {
+                Ok(()) => ack.ok(),
+                Err(reason) => ack.fail(reason),
+            },
+            Event::RequestStart {
+                connection_id,
+                request_id,
+                client_addr,
+                method,
+                uri,
+                version,
+                headers,
+                rest,
+                more_body,
+                meta,
+                resp_tx,
+            } => {
+                let body = if more_body {
+                    let (body_tx, mut body) = Body::channel(rest);
+                    if meta.expect_continue {
+                        body.send_continue_on_read(resp_tx.downgrade());
+                    }
+                    bodies.insert(request_id, body_tx);
+                    body
+                } else {
+                    Body::full(rest)
+                };
+                let request = Request {
+                    connection_id,
+                    request_id,
+                    client_addr,
+                    method,
+                    uri,
+                    version,
+                    headers,
+                    meta,
+                    params: PathParams::default(),
+                    body,
+                };
+
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    let mut response = handler.call(request).await;
+                    match response.take_event_stream() {
+                        Some(stream) => {
+                            // Runs until the handler drops its sender or
+                            // the client goes away.
+                            if stream.serve(resp_tx, response).await.is_err() {
+                                debug!(request = %request_id, "Client left the event stream");
+                            }
+                        }
+                        None => {
+                            if resp_tx.send(response).is_err() {
+                                debug!(request = %request_id, "Connection closed before the response was sent");
+                            }
+                        }
+                    }
+                });
+            }
+            Event::RequestBody {
+                request_id,
+                body,
+                more_body,
+                ..
+            } => {
+                if let Some(body_tx) = bodies.get(&request_id) {
+                    // The handler may have stopped reading the body.
+                    let _ = body_tx.send((body, more_body));
+                }
+                if !more_body {
+                    bodies.remove(&request_id);
+                }
+            }
+            Event::RequestRejected {
+                request_id,
+                status,
+                reason,
+                ..
+            } => {
+                warn!(status = status.code(), "Request rejected: {}", reason);
+                if let Some(id) = request_id {
+                    bodies.remove(&id);
+                }
+            }
+            Event::RequestTimeout {
+                request_id,
+                timeout,
+                ..
+            } => {
+                warn!("Request abandoned: {}", timeout);
+                if let Some(id) = request_id {
+                    bodies.remove(&id);
+                }
+            }
+            Event::WebSocketConnect {
+                connection_id,
+                request_id,
+                client_addr,
+                uri,
+                headers,
+                protocols,
+                ws_tx,
+            } => {
+                let request = Request {
+                    connection_id,
+                    request_id,
+                    client_addr,
+                    method: Method::Get,
+                    uri,
+                    version: 1,
+                    headers,
+                    meta: RequestMeta::new(),
+                    params: PathParams::default(),
+                    body: Body::default(),
+                };
+                let (messages, socket) = WebSocket::new(request, protocols, ws_tx);
+                sockets.insert(request_id, messages);
+
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move { handler.websocket(socket).await });
+            }
+            Event::WebSocketReceive {
+                request_id,
+                message,
+                ..
+            } => {
+                if let Some(messages) = sockets.get(&request_id) {
+                    if !messages.send(message) {
+                        // Closed with 1008; drop what else arrives.
+                        sockets.remove(&request_id);
+                    }
+                }
+            }
+            Event::WebSocketDisconnect {
+                request_id, code, ..
+            } => {
+                debug!(request = %request_id, code, "WebSocket closed");
+                // Dropping the sender ends `WebSocket::recv`.
+                sockets.remove(&request_id);
+            }
+            Event::Disconnect { request_id, .. } => {
+                // Dropping the sender fails the pending body read.
+                if let Some(id) = request_id {
+                    bodies.remove(&id);
+                }
+            }
+            Event::Shutdown { ack } => {
+                handler.shutdown().await;
+                ack.ok();
+                info!("Server shut down");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::HttpStatus;
+    use crate::core::response::Response;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn spawn_server(handler: impl Handler) -> SocketAddr {
+        let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::builder()
+            .listener(listener)
+            .config(ServerConfig {
+                addr: addr.into(),
+                max_payload_size: 1024,
+                read_buffer_size: 16,
+                ..ServerConfig::default()
+            })
+            .handler(handler)
+            .build()
+            .unwrap();
+        tokio::spawn(server.run());
+        addr
+    }
+
+    #[test]
+    fn test_build_requires_handler() {
+        assert!(matches!(
+            Server::builder().build(),
+            Err(BuildError::MissingHandler)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handler_receives_streamed_body() {
+        let addr = spawn_server(|request: Request| async move {
+            let path = request.uri.path.clone();
+            let body = request.body.collect().await.unwrap();
+            let mut out = path.into_bytes();
+            out.push(b' ');
+            out.extend_from_slice(&body);
+            Response::ok().body(out)
+        })
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let payload = "x".repeat(100);
+        let raw = format!(
+            "POST /echo HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            payload.len(),
+            payload
+        );
+        client.write_all(raw.as_bytes()).await.unwrap();
+
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with(&format!("\r\n\r\n/echo {}", payload)));
+    }
+
+    #[tokio::test]
+    async fn test_reading_the_body_sends_continue() {
+        let addr = spawn_server(|request: Request| async move {
+            if request.headers.get_str("Authorization").is_none() {
+                return Response::error(HttpStatus::Unauthorized);
+            }
+            let body = request.body.collect().await.unwrap();
+            Response::ok().body(body)
+        })
+        .await;
+        let upload = "PUT / HTTP/1.1\r\nContent-Length: 2\r\nExpect: 100-continue\r\n";
+
+        // 1. Authorized: the handler reads the body, which asks for it.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let raw = format!("{}Authorization: yes\r\nConnection: close\r\n\r\n", upload);
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut interim = [0u8; 25];
+        client.read_exact(&mut interim).await.unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+        client.write_all(b"ok").await.unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with("\r\n\r\nok"));
+
+        // 2. Unauthorized: answered before the body is sent.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(format!("{}\r\n", upload).as_bytes())
+            .await
+            .unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        assert!(out.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_dispatched_concurrently() {
+        let (release_tx, release_rx) = tokio::sync::watch::channel(false);
+        let addr = spawn_server(move |request: Request| {
+            let mut release_rx = release_rx.clone();
+            async move {
+                // The slow request waits until the fast one has been answered.
+                if request.uri.path == "/slow" {
+                    let _ = release_rx.wait_for(|released| *released).await;
+                }
+                Response::ok().body(request.uri.path.into_bytes())
+            }
+        })
+        .await;
+
+        let mut slow = TcpStream::connect(addr).await.unwrap();
+        slow.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut fast = TcpStream::connect(addr).await.unwrap();
+        fast.write_all(b"GET /fast HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut out = String::new();
+        fast.read_to_string(&mut out).await.unwrap();
+        assert!(out.ends_with("/fast"));
+
+        release_tx.send(true).unwrap();
+        let mut out = String::new();
+        slow.read_to_string(&mut out).await.unwrap();
+        assert!(out.ends_with("/slow"));
+    }
+
+    #[tokio::test]
+    async fn test_run_returns_after_shutdown() {
+        let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let server = Server::builder()
+            .listener(listener)
+            .config(ServerConfig::default())
+            .handler(|_request: Request| async { Response::ok() })
+            .build()
+            .unwrap();
+        let handle = server.handle();
+
+        let running = tokio::spawn(server.run());
+        handle.shutdown();
+        running.await.unwrap().unwrap();
+    }
+
+    struct Lifespan {
+        veto: bool,
+        shut_down: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl Handler for Lifespan {
+        async fn call(&self, _request: Request) -> Response {
+            Response::ok()
+        }
+
+        async fn startup(&self) -> Result<(), String> {
+            if self.veto {
+                Err("cache warm-up failed".to_string())
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn shutdown(&self) {
+            self.shut_down
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    async fn lifespan_server(veto: bool) -> (Server, Arc<std::sync::atomic::AtomicBool>) {
+        let shut_down = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server = Server::builder()
+            .listener(
+                TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+                    .await
+                    .unwrap(),
+            )
+            .config(ServerConfig::default())
+            .handler(Lifespan {
+                veto,
+                shut_down: Arc::clone(&shut_down),
+            })
+            .build()
+            .unwrap();
+        (server, shut_down)
+    }
+
+    #[tokio::test]
+    async fn test_startup_veto_fails_run() {
+        let (server, _) = lifespan_server(true).await;
+
+        let err = server.run().await.unwrap_err();
+        assert!(err.to_string().contains("cache warm-up failed"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_hook_runs_before_run_returns() {
+        let (server, shut_down) = lifespan_server(false).await;
+        let handle = server.handle();
+
+        handle.shutdown();
+        server.run().await.unwrap();
+        assert!(shut_down.load(std::sync::atomic::Ordering::SeqCst));
+    }
+}
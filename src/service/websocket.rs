@@ -0,0 +1,144 @@
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+
+use crate::core::headers::HeaderMap;
+use crate::core::response::Response;
+use crate::core::websocket::{close_code, Message, WebSocketError, WebSocketSend, WebSocketSender};
+
+use super::request::Request;
+
+// Messages queued for a handler that isn't reading. Each one can be up to
+// `websocket_max_message_size` bytes.
+const MESSAGE_QUEUE_CAPACITY: usize = 16;
+
+/// Sending half of a [`WebSocket`]'s incoming messages, fed by the
+/// dispatcher from `WebSocketReceive` events.
+#[derive(Debug)]
+pub(crate) struct MessageSender {
+    tx: mpsc::Sender<Message>,
+    // Weak, so the handler dropping its `WebSocket` still closes it.
+    close_tx: mpsc::WeakSender<WebSocketSend>,
+}
+
+impl MessageSender {
+    /// Queues `message` without waiting, so one slow handler can't hold up
+    /// the dispatcher. Once the queue is full the WebSocket is closed with
+    /// 1008 and `false` is returned; the sender should then be dropped.
+    pub(crate) fn send(&self, message: Message) -> bool {
+        match self.tx.try_send(message) {
+            // The handler may have stopped reading.
+            Ok(()) | Err(TrySendError::Closed(_)) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("WebSocket handler fell behind, closing the connection");
+                let Some(close_tx) = self.close_tx.upgrade() else {
+                    return false;
+                };
+                tokio::spawn(async move {
+                    let close = WebSocketSend::Close {
+                        code: close_code::POLICY_VIOLATION,
+                        reason: "message queue full".to_string(),
+                    };
+                    let _ = close_tx.send(close).await;
+                });
+                false
+            }
+        }
+    }
+}
+
+/// A WebSocket upgrade request handed to
+/// [`Handler::websocket`](super::handler::Handler::websocket).
+///
+/// Answer it with [`accept`](Self::accept) or [`reject`](Self::reject),
+/// then exchange messages until [`recv`](Self::recv) returns `None`.
+#[derive(Debug)]
+pub struct WebSocket {
+    /// The upgrade request. Its body is always empty.
+    pub request: Request,
+    /// Subprotocols the client offered, most preferred first.
+    pub protocols: Vec<String>,
+    sender: WebSocketSender,
+    incoming: mpsc::Receiver<Message>,
+}
+
+impl WebSocket {
+    pub(crate) fn new(
+        request: Request,
+        protocols: Vec<String>,
+        sender: WebSocketSender,
+    ) -> (MessageSender, Self) {
+        let (tx, incoming) = mpsc::channel(MESSAGE_QUEUE_CAPACITY);
+        let messages = MessageSender {
+            tx,
+            close_tx: sender.downgrade(),
+        };
+        let socket = Self {
+            request,
+            protocols,
+            sender,
+            incoming,
+        };
+        (messages, socket)
+    }
+
+    /// Completes the handshake, optionally picking one of `protocols`.
+    /// Headers the 101 response carries once accepted, such as those
+    /// middleware added.
+    pub(crate) fn handshake_headers(&mut self, headers: HeaderMap) {
+        self.sender.handshake_headers(headers);
+    }
+
+    pub async fn accept(&mut self, protocol: Option<&str>) -> Result<(), WebSocketError> {
+        self.sender.accept(protocol).await
+    }
+
+    /// Answers the handshake with `response` instead.
+    pub async fn reject(self, response: Response) -> Result<(), WebSocketError> {
+        self.sender.reject(response).await
+    }
+
+    /// Next message from the client, `None` once the WebSocket is closed.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.incoming.recv().await
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        self.sender.send(message).await
+    }
+
+    /// Starts the closing handshake; `recv` returns `None` once it is done.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.sender.close(code, reason).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::enums::Method;
+    use crate::core::uri::Uri;
+
+    #[tokio::test]
+    async fn test_full_queue_closes_with_1008() {
+        let (ws_tx, mut ws_rx) = WebSocketSender::channel();
+        let request = Request::new(Method::Get, Uri::parse("/ws").unwrap());
+        let (messages, mut socket) = WebSocket::new(request, Vec::new(), ws_tx);
+
+        for i in 0..MESSAGE_QUEUE_CAPACITY {
+            assert!(messages.send(Message::Text(i.to_string())));
+        }
+        assert!(!messages.send(Message::Text("one too many".to_string())));
+
+        assert!(matches!(
+            ws_rx.recv().await,
+            Some(WebSocketSend::Close {
+                code: close_code::POLICY_VIOLATION,
+                ..
+            })
+        ));
+        // What was queued is still delivered.
+        drop(messages);
+        assert_eq!(socket.recv().await, Some(Message::Text("0".to_string())));
+    }
+}
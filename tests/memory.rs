@@ -0,0 +1,314 @@
+//! The whole request pipeline over `MemoryListener`, without sockets.
+
+use std::sync::Arc;
+
+use aegis::core::enums::HttpStatus;
+use aegis::{
+    run_server, Event, MemoryConnector, MemoryListener, Response, ServerConfig, TestClient,
+};
+use tokio::sync::mpsc;
+
+fn config() -> ServerConfig {
+    ServerConfig {
+        max_payload_size: 128,
+        ..ServerConfig::default()
+    }
+}
+
+/// Runs the event API, answering every request with its client address.
+fn spawn_event_server() -> MemoryConnector {
+    let (listener, connector) = MemoryListener::new();
+    let (tx, mut rx) = mpsc::channel(16);
+    tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config())));
+
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Event::RequestStart {
+                client_addr,
+                resp_tx,
+                ..
+            } = event
+            {
+                let _ = resp_tx.send(Response::ok().body(client_addr.to_string().into_bytes()));
+            }
+        }
+    });
+    connector
+}
+
+#[tokio::test]
+async fn test_event_api_over_memory() {
+    let connector = spawn_event_server();
+    let peer = aegis::PeerAddr::Tcp("192.0.2.7:5150".parse().unwrap());
+    let mut client = TestClient::new(connector.connect_from(peer).await.unwrap());
+
+    let response = client.request(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    assert_eq!(response.status, HttpStatus::Ok);
+    assert_eq!(response.body, b"192.0.2.7:5150");
+}
+
+#[tokio::test]
+async fn test_oversized_body_is_rejected() {
+    let connector = spawn_event_server();
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+
+    let response = client
+        .request(b"POST / HTTP/1.1\r\nContent-Length: 129\r\n\r\n")
+        .await
+        .unwrap();
+    assert_eq!(response.status, HttpStatus::ContentTooLarge);
+    assert!(response.closes_connection());
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_router_over_memory() {
+    use aegis::{Request, Router, Server};
+
+    let (listener, connector) = MemoryListener::new();
+    let router = Router::new().post("/echo/:name", |request: Request| async move {
+        let name = request.params.get("name").unwrap_or_default().to_string();
+        let body = request.body.collect().await.unwrap_or_default();
+        Response::ok().body([name.as_bytes(), b":", &body].concat())
+    });
+    let server = Server::builder()
+        .listener(listener)
+        .config(config())
+        .handler(router)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+    let echoed = client
+        .request(
+            b"POST /echo/aegis HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    assert_eq!(echoed.body, b"aegis:hi");
+
+    let missing = client
+        .request(b"GET /echo/aegis HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    assert_eq!(missing.status, HttpStatus::MethodNotAllowed);
+    assert_eq!(missing.headers.get_str("Allow"), Some("POST, OPTIONS"));
+    assert!(client.read_to_close().await.unwrap().is_empty());
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_websocket_handler_over_memory() {
+    use aegis::{Handler, Message, Next, Request, Router, Server, WebSocket};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl Handler for Echo {
+        async fn call(&self, _request: Request) -> Response {
+            Response::not_found()
+        }
+
+        async fn websocket(&self, mut socket: WebSocket) {
+            assert_eq!(socket.request.uri.path, "/ws");
+            socket.accept(None).await.unwrap();
+            while let Some(message) = socket.recv().await {
+                if let Message::Text(text) = message {
+                    let _ = socket.send(Message::Text(text.to_uppercase())).await;
+                }
+            }
+        }
+    }
+
+    let cookie = |request: Request, next: Next| async move {
+        next.run(request).await.header("Set-Cookie", "seen=1")
+    };
+    let (listener, connector) = MemoryListener::new();
+    let server = Server::builder()
+        .listener(listener)
+        .handler(Router::new().get("/ws", Echo).layer(cookie))
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+    client
+        .send(
+            b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let response = client.read_response().await.unwrap();
+    assert_eq!(response.status, HttpStatus::SwitchingProtocols);
+    assert_eq!(response.headers.get_str("Set-Cookie"), Some("seen=1"));
+
+    // Masked text frame "hi", with an all-zero mask.
+    let mut io = client.into_inner();
+    io.write_all(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i'])
+        .await
+        .unwrap();
+    let mut frame = [0u8; 4];
+    io.read_exact(&mut frame).await.unwrap();
+    assert_eq!(frame, [0x81, 0x02, b'H', b'I']);
+}
+
+const UPGRADE: &[u8] = b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+/// Serves `handler`, which sees WebSocket upgrades only.
+#[cfg(feature = "service")]
+fn spawn_websocket_server<F, Fut>(handler: F) -> MemoryConnector
+where
+    F: Fn(aegis::WebSocket) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    use aegis::{Handler, Request, Server, WebSocket};
+
+    struct Sockets<F>(F);
+
+    #[async_trait::async_trait]
+    impl<F, Fut> Handler for Sockets<F>
+    where
+        F: Fn(WebSocket) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        async fn call(&self, _request: Request) -> Response {
+            Response::not_found()
+        }
+
+        async fn websocket(&self, socket: WebSocket) {
+            (self.0)(socket).await
+        }
+    }
+
+    let (listener, connector) = MemoryListener::new();
+    let server = Server::builder()
+        .listener(listener)
+        .handler(Sockets(handler))
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    connector
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_websocket_closes_when_the_handler_returns() {
+    use aegis::Message;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let connector = spawn_websocket_server(|mut socket| async move {
+        socket.accept(None).await.unwrap();
+        socket.send(Message::Text("bye".to_string())).await.unwrap();
+    });
+
+    // Read by hand: the frames may arrive together with the 101.
+    let mut io = connector.connect().await.unwrap();
+    io.write_all(UPGRADE).await.unwrap();
+    let mut raw = Vec::new();
+    let frames = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let Some(end) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                if raw.len() >= end + 4 + 9 {
+                    return raw[end + 4..].to_vec();
+                }
+            }
+            let mut chunk = [0u8; 256];
+            let n = io.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed early");
+            raw.extend_from_slice(&chunk[..n]);
+        }
+    })
+    .await
+    .expect("no close frame after the handler returned");
+
+    assert!(raw.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+    // The message, then a close frame with code 1000.
+    assert_eq!(
+        frames,
+        [0x81, 0x03, b'b', b'y', b'e', 0x88, 0x02, 0x03, 0xe8]
+    );
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_unanswered_websocket_upgrade_gets_403() {
+    use std::time::Duration;
+
+    let connector = spawn_websocket_server(|socket| async move { drop(socket) });
+
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+    let response = tokio::time::timeout(Duration::from_secs(2), client.request(UPGRADE))
+        .await
+        .expect("no answer after the handler dropped the upgrade")
+        .unwrap();
+    assert_eq!(response.status, HttpStatus::Forbidden);
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_event_stream_over_memory() {
+    use aegis::core::sse;
+    use aegis::{Request, Server, SseEvent};
+    use tokio::sync::oneshot;
+
+    let (closed_tx, closed_rx) = oneshot::channel();
+    let closed_tx = Arc::new(std::sync::Mutex::new(Some(closed_tx)));
+    let handler = move |request: Request| {
+        let closed_tx = Arc::clone(&closed_tx);
+        async move {
+            let next = request
+                .last_event_id()
+                .and_then(|id| id.parse::<u32>().ok())
+                .map_or(0, |id| id + 1);
+            let (events, stream) = sse::channel();
+            tokio::spawn(async move {
+                if request.uri.path == "/finite" {
+                    for id in next..next + 2 {
+                        let event = SseEvent::new(format!("tick {}", id)).id(&id.to_string());
+                        events.send(event).await.unwrap();
+                    }
+                    return;
+                }
+                // Streams until the client goes away.
+                let _ = events.send(SseEvent::new("hello")).await;
+                events.closed().await;
+                let _ = closed_tx.lock().unwrap().take().unwrap().send(());
+            });
+            Response::ok().event_stream(stream)
+        }
+    };
+
+    let (listener, connector) = MemoryListener::new();
+    let server = Server::builder()
+        .listener(listener)
+        .handler(handler)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+    let response = client
+        .request(b"GET /finite HTTP/1.1\r\nLast-Event-ID: 4\r\n\r\n")
+        .await
+        .unwrap();
+    assert_eq!(response.status, HttpStatus::Ok);
+    assert_eq!(
+        response.headers.get_str("Content-Type"),
+        Some("text/event-stream")
+    );
+    assert_eq!(
+        response.body,
+        b"id: 5\ndata: tick 5\n\nid: 6\ndata: tick 6\n\n"
+    );
+
+    // Hanging up ends the stream on the server side as well.
+    let mut client = TestClient::new(connector.connect().await.unwrap());
+    client.send(b"GET /open HTTP/1.1\r\n\r\n").await.unwrap();
+    drop(client);
+    closed_rx.await.unwrap();
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use aegis::core::enums::Method;
+use aegis::protocols::tcp::listener::TcpByteListener;
+use aegis::{run_server, Event, Response, ServerConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+fn config(addr: std::net::SocketAddr) -> ServerConfig {
+    ServerConfig {
+        addr: addr.into(),
+        max_payload_size: 1024,
+        read_buffer_size: 1024,
+        ..ServerConfig::default()
+    }
+}
+
+async fn request(addr: std::net::SocketAddr, raw: &[u8]) -> String {
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(raw).await.unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).await.unwrap();
+    out
+}
+
+#[tokio::test]
+async fn test_run_server_event_api() {
+    let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, mut rx) = mpsc::channel(16);
+    tokio::spawn(run_server(Box::new(listener), tx, Arc::new(config(addr))));
+
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Event::RequestStart {
+                method, resp_tx, ..
+            } = event
+            {
+                assert_eq!(method, Method::Get);
+                let _ = resp_tx.send(Response::ok().body(b"events".to_vec()));
+            }
+        }
+    });
+
+    let out = request(addr, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(out.ends_with("\r\n\r\nevents"));
+}
+
+#[cfg(feature = "service")]
+#[tokio::test]
+async fn test_server_builder_api() {
+    use aegis::{Request, Server};
+
+    let listener = TcpByteListener::bind("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .listener(listener)
+        .config(config(addr))
+        .handler(
+            |request: Request| async move { Response::ok().body(request.uri.path.into_bytes()) },
+        )
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+
+    let out = request(addr, b"GET /library HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(out.ends_with("\r\n\r\n/library"));
+}